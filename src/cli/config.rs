@@ -1,11 +1,32 @@
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::cli::opts::Opts;
 use crate::cli::summaries::print_intro;
+use crate::export::abi::{get_events, Event};
+use crate::export::naming::{get_file_name, DEFAULT_FILE_TEMPLATE, DEFAULT_PARTITION_TEMPLATE};
+use crate::fetcher::concurrency::{Concurrency, DEFAULT_CONCURRENCY, DEFAULT_MAX_CONCURRENCY};
+use crate::fetcher::retry::RetryPolicy;
+use crate::manifest::Manifest;
+use crate::report::FailureReport;
 use anyhow::{anyhow, Ok, Result};
-//use utils::archive::get_height;
+use serde::{Deserialize, Serialize};
+use utils::archive::get_height;
+use utils::utils::fnv1a_hash;
 
-#[derive(Debug, PartialEq, Clone)]
+const DEFAULT_START_BLOCK: u64 = 1;
+//starts a new selection group in `-o`
+const OPTION_GROUP_SEPARATOR: &str = "or";
+const DEFAULT_POLL_INTERVAL: u64 = 12;
+const DEFAULT_OUTPUT_DIR: &str = "data";
+const DEFAULT_PARTITION_SIZE: u64 = 1000000;
+const DEFAULT_MEMORY_BUDGET: u64 = 1024;
+const DEFAULT_REQUEST_TIMEOUT: u64 = 60;
+const ARCHIVE_URL: &str = "https://v2.archive.subsquid.io/network";
+const DEFAULT_NETWORK: &str = "ethereum-mainnet";
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Range {
     pub start: u64,
     pub end: u64,
@@ -16,13 +37,96 @@ pub enum Dataset {
     Blocks,
     Transactions,
     Logs,
+    Traces,
+    StateDiffs,
+}
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Parquet,
+    Csv,
+    JsonLines,
+    ArrowIpc,
+}
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Partition {
+    //buckets of this many blocks
+    BlockBucket(u64),
+    //utc day of the block timestamp
+    Date,
+}
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+    Uncompressed,
+    Snappy,
+    Gzip(Option<u8>),
+    Lz4,
+    Zstd(Option<i32>),
+}
+//how 256-bit quantities like wei values are written
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum U256Format {
+    //decimal digits
+    String,
+    //32 bytes, big-endian
+    Binary,
+    //Decimal128, null when the value needs more than 38 digits
+    Decimal,
+    //`<field>_hi` and `<field>_lo` columns with 16 big-endian bytes each
+    HiLo,
+}
+//filter values by option, a row has to match every option of the selection
+pub type Selection = HashMap<String, Vec<String>>;
+
+//one dataset of the run with its own fields and options
+#[derive(Debug, Clone)]
+pub struct DatasetConfig {
+    pub dataset: Dataset,
+    pub fields: Vec<String>,
+    pub options: Vec<Selection>,
+    //companion output holding the rows related to the other datasets' selections
+    pub related: bool,
+}
+//only used when writing parquet
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ParquetOptions {
+    pub compression: Compression,
+    pub row_group_size: Option<usize>,
+    pub statistics: bool,
 }
 #[derive(Debug, Clone)]
 pub struct Config {
+    //the dataset being written, the first one of `datasets` outside of the writer
     pub dataset: Dataset,
     pub range: Range,
     pub fields: Vec<String>,
-    pub options: HashMap<String, Vec<String>>,
+    //selection groups, a row is fetched if it matches any of them
+    pub options: Vec<Selection>,
+    //every dataset fetched by the run, all of them come from one archive query
+    pub datasets: Vec<DatasetConfig>,
+    pub network: String,
+    pub archive_url: String,
+    pub follow: bool,
+    pub poll_interval: Duration,
+    pub confirmations: u64,
+    pub format: OutputFormat,
+    pub output_dir: PathBuf,
+    pub file_template: String,
+    pub partition: Option<Partition>,
+    pub blocks_per_file: Option<u64>,
+    pub parquet: ParquetOptions,
+    pub u256: U256Format,
+    pub binary: bool,
+    //events decoded from the logs, each into a table of its own
+    pub events: Vec<Event>,
+    //the event table being written, instead of the dataset
+    pub event: Option<Event>,
+    //only these ranges are fetched when retrying a failure report
+    pub retry_ranges: Option<Vec<Range>>,
+    pub retry: RetryPolicy,
+    pub request_timeout: Duration,
+    pub concurrency: Concurrency,
+    //bytes
+    pub memory_budget: u64,
 }
 
 impl Dataset {
@@ -31,6 +135,8 @@ impl Dataset {
             Dataset::Blocks => "blocks",
             Dataset::Transactions => "transactions",
             Dataset::Logs => "logs",
+            Dataset::Traces => "traces",
+            Dataset::StateDiffs => "state_diffs",
         }
     }
     pub fn get_chunk_size(&self) -> u64 {
@@ -38,6 +144,166 @@ impl Dataset {
             Dataset::Blocks => 10000,
             Dataset::Transactions => 1000,
             Dataset::Logs => 5000,
+            Dataset::Traces => 1000,
+            Dataset::StateDiffs => 1000,
+        }
+    }
+}
+
+impl Config {
+    pub async fn from_opts(opts: Opts) -> Result<Config> {
+        let height = if opts.retry_failed.is_none() && needs_height(&opts.range) {
+            let (_, archive_url) = get_network(opts.network.clone(), opts.archive_url.clone())?;
+            let height = get_height(&archive_url).await?.trim().parse::<u64>()?;
+            Some(height.saturating_sub(opts.confirmations.unwrap_or(0)))
+        } else {
+            None
+        };
+        build_config(opts, height)
+    }
+}
+
+impl Config {
+    //the same config, writing the table of the given event
+    pub fn for_event(&self, event: &Event) -> Config {
+        Config {
+            event: Some(event.clone()),
+            ..self.clone()
+        }
+    }
+    //name of the output, e.g. `logs` or `logs_Transfer` for a decoded event
+    pub fn get_table_name(&self) -> String {
+        match &self.event {
+            Some(event) => format!("{}_{}", self.dataset.get_name(), event.table),
+            None => self.dataset.get_name().to_owned(),
+        }
+    }
+    //the same config, writing only the given dataset
+    pub fn for_dataset(&self, dataset: &DatasetConfig) -> Config {
+        Config {
+            dataset: dataset.dataset,
+            fields: dataset.fields.clone(),
+            options: dataset.options.clone(),
+            datasets: vec![dataset.clone()],
+            ..self.clone()
+        }
+    }
+    //e.g. `blocks,transactions,logs`, as given to `-d`
+    pub fn get_datasets_name(&self) -> String {
+        self.datasets
+            .iter()
+            .map(|dataset| dataset.dataset.get_name())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+    //the smallest chunk of the datasets, so every query stays small enough
+    pub fn get_chunk_size(&self) -> u64 {
+        self.datasets
+            .iter()
+            .map(|dataset| dataset.dataset.get_chunk_size())
+            .min()
+            .unwrap_or(self.dataset.get_chunk_size())
+    }
+    pub fn get_ranges(&self) -> Vec<Range> {
+        match &self.retry_ranges {
+            Some(ranges) => ranges.clone(),
+            None => vec![self.range.clone()],
+        }
+    }
+    pub fn fields_hash(&self) -> String {
+        let mut fields = self.fields.clone();
+        fields.sort();
+        format!("{:016x}", fnv1a_hash(fields.join(",").as_bytes()))[..8].to_owned()
+    }
+    //header fields requested for every dataset, regardless of the selected fields
+    pub fn header_fields(&self) -> Vec<&str> {
+        let mut header_fields: Vec<&str> = self
+            .datasets
+            .iter()
+            .flat_map(|dataset| {
+                dataset
+                    .fields
+                    .iter()
+                    .filter_map(|field| get_context_field(dataset.dataset, field))
+            })
+            .collect();
+        if self.partition == Some(Partition::Date) {
+            header_fields.push("timestamp");
+        }
+        header_fields.sort();
+        header_fields.dedup();
+        header_fields
+    }
+}
+
+impl Partition {
+    pub fn get_name(&self) -> String {
+        match self {
+            Partition::BlockBucket(size) => format!("block_bucket({})", size),
+            Partition::Date => "date".to_owned(),
+        }
+    }
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        ParquetOptions {
+            compression: Compression::Snappy,
+            row_group_size: None,
+            statistics: false,
+        }
+    }
+}
+
+impl Compression {
+    pub fn get_name(&self) -> String {
+        match self {
+            Compression::Uncompressed => "uncompressed".to_owned(),
+            Compression::Snappy => "snappy".to_owned(),
+            Compression::Gzip(None) => "gzip".to_owned(),
+            Compression::Gzip(Some(level)) => format!("gzip({})", level),
+            Compression::Lz4 => "lz4".to_owned(),
+            Compression::Zstd(None) => "zstd".to_owned(),
+            Compression::Zstd(Some(level)) => format!("zstd({})", level),
+        }
+    }
+}
+
+impl U256Format {
+    pub fn get_name(&self) -> &str {
+        match self {
+            U256Format::String => "string",
+            U256Format::Binary => "binary",
+            U256Format::Decimal => "decimal",
+            U256Format::HiLo => "hilo",
+        }
+    }
+    //type of the column a u256 field is written as
+    pub fn get_type(&self) -> &'static str {
+        match self {
+            U256Format::String => "decimal string",
+            U256Format::Binary => "binary",
+            U256Format::Decimal => "decimal",
+            U256Format::HiLo => "binary hi/lo",
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn get_name(&self) -> &str {
+        match self {
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Csv => "csv",
+            OutputFormat::JsonLines => "jsonl",
+            OutputFormat::ArrowIpc => "arrow-ipc",
+        }
+    }
+    pub fn get_extension(&self) -> &str {
+        match self {
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Csv => "csv",
+            OutputFormat::JsonLines => "jsonl",
+            OutputFormat::ArrowIpc => "arrow",
         }
     }
 }
@@ -45,23 +311,120 @@ impl Dataset {
 impl TryFrom<Opts> for Config {
     type Error = anyhow::Error;
     fn try_from(opts: Opts) -> Result<Self> {
-        let dataset = get_dataset(opts.dataset)?;
-        let range = get_range(opts.range)?.try_into()?;
-        let fields = get_fields(opts.fields, dataset)?;
-        let options = get_options(opts.options, dataset.clone())?;
-        print_intro(
-            dataset,
-            &fields,
-            &range,
-            &options.values().flatten().collect::<Vec<&String>>(),
-        );
-        Ok(Config {
-            dataset,
-            range,
-            fields,
-            options,
+        build_config(opts, None)
+    }
+}
+
+fn build_config(opts: Opts, height: Option<u64>) -> Result<Config> {
+    let format = get_format(opts.format.clone())?;
+    let parquet = get_parquet_options(&opts, format)?;
+    let mut datasets = get_datasets(opts.dataset)?;
+    let selected = datasets.len();
+    datasets.extend(get_related_datasets(
+        &datasets,
+        opts.include_transactions,
+        opts.include_logs,
+    )?);
+    let report = match &opts.retry_failed {
+        Some(path) => Some(FailureReport::load(Path::new(path))?),
+        None => None,
+    };
+    //a retry keeps the original range so file boundaries stay the same
+    let range = match &report {
+        Some(report) => report.range.clone(),
+        None => get_range(opts.range, height)?,
+    };
+    let fields = split_by_dataset(opts.fields, &datasets, is_valid_field)?;
+    let options = split_by_dataset(opts.options, &datasets, is_valid_option)?;
+    let datasets = datasets
+        .into_iter()
+        .zip(fields.into_iter().zip(options))
+        .enumerate()
+        .map(|(index, (dataset, (fields, options)))| {
+            let related = index >= selected;
+            let options = get_options(options, dataset)?;
+            if related && !options.is_empty() {
+                return Err(anyhow!(
+                    "Options do not apply to the included {}",
+                    dataset.get_name()
+                ));
+            }
+            Ok(DatasetConfig {
+                dataset,
+                fields: get_fields(fields, dataset)?,
+                options,
+                related,
+            })
         })
+        .collect::<Result<Vec<_>>>()?;
+    let first = datasets[0].clone();
+    let (network, archive_url) = get_network(opts.network, opts.archive_url)?;
+    let partition = get_partition(opts.partition, opts.partition_size)?;
+    let config = Config {
+        dataset: first.dataset,
+        range,
+        fields: first.fields,
+        options: first.options,
+        datasets,
+        network,
+        archive_url,
+        follow: opts.follow,
+        poll_interval: Duration::from_secs(opts.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL)),
+        confirmations: opts.confirmations.unwrap_or(0),
+        format,
+        output_dir: PathBuf::from(opts.output_dir.unwrap_or(DEFAULT_OUTPUT_DIR.to_owned())),
+        file_template: opts.file_template.unwrap_or(match partition {
+            Some(_) => DEFAULT_PARTITION_TEMPLATE.to_owned(),
+            None => DEFAULT_FILE_TEMPLATE.to_owned(),
+        }),
+        partition,
+        blocks_per_file: get_blocks_per_file(opts.blocks_per_file)?,
+        parquet,
+        u256: get_u256_format(opts.u256)?,
+        binary: get_binary(opts.binary, format)?,
+        events: get_events(opts.events, opts.abi)?,
+        event: None,
+        retry_ranges: report
+            .as_ref()
+            .map(|report| report.failed.iter().map(|f| f.get_range()).collect()),
+        retry: get_retry_policy(opts.max_attempts, opts.max_backoff, opts.jitter)?,
+        request_timeout: get_request_timeout(opts.request_timeout)?,
+        concurrency: get_concurrency(opts.concurrency, opts.max_concurrency)?,
+        memory_budget: get_memory_budget(opts.memory_budget, opts.blocks_per_file)?,
+    };
+    if let Some(report) = &report {
+        if report.network != config.network || report.dataset != config.get_datasets_name() {
+            return Err(anyhow!(
+                "Failure report is for {} {}, not {} {}",
+                report.network,
+                report.dataset,
+                config.network,
+                config.get_datasets_name()
+            ));
+        }
+        if report.key != Manifest::load(&config)?.get_key() {
+            return Err(anyhow!(
+                "Failure report was written with other fields, options or output settings, \
+                 rerun with the options of the failed run"
+            ));
+        }
+    }
+    let has_logs = config.datasets.iter().any(|d| d.dataset == Dataset::Logs);
+    if !config.events.is_empty() && !has_logs {
+        return Err(anyhow!("Decoding events needs the logs dataset"));
     }
+    //every dataset and event gets its own files
+    let has_dataset = config.file_template.contains("{dataset");
+    let outputs = config.datasets.len() + config.events.len();
+    if outputs > 1 && config.partition.is_none() && !has_dataset {
+        return Err(anyhow!(
+            "File template must contain {{dataset}} when writing several datasets or events"
+        ));
+    }
+    //fail on a bad template now rather than after the first fetch
+    get_file_name(&config, config.range.start, config.range.end)?;
+    print_intro(&config);
+    Ok(config)
 }
 
 impl TryFrom<String> for Dataset {
@@ -71,43 +434,96 @@ impl TryFrom<String> for Dataset {
             "blocks" => Ok(Dataset::Blocks),
             "transactions" => Ok(Dataset::Transactions),
             "logs" => Ok(Dataset::Logs),
+            "traces" => Ok(Dataset::Traces),
+            "state_diffs" => Ok(Dataset::StateDiffs),
             _ => Err(anyhow!("Invalid dataset")),
         }
     }
 }
 
-impl TryFrom<Vec<String>> for Range {
-    type Error = anyhow::Error;
-    fn try_from(value: Vec<String>) -> Result<Self> {
-        if value.len() == 0 {
-            return Ok(Range {
-                start: 1,
-                end: 18000000,
-            });
+//open-ended and latest-relative ranges are resolved against the archive height
+fn get_range(range: Option<String>, height: Option<u64>) -> Result<Range> {
+    let range = range.unwrap_or_default();
+    let (start, end) = if range.is_empty() {
+        ("", "")
+    } else {
+        range
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid range {}, expected START:END", range))?
+    };
+
+    let start = match start {
+        "" => DEFAULT_START_BLOCK,
+        start => get_block_number(start, height)?,
+    };
+    //a number as the end is exclusive, `latest` and `latest-N` name the last block to fetch
+    let end = match end {
+        "" => get_block_number("latest", height)? + 1,
+        end if end.starts_with("latest") => get_block_number(end, height)? + 1,
+        end => get_block_number(end, height)?,
+    };
+
+    if start >= end {
+        return Err(anyhow!(
+            "Invalid range {}:{}, start must be before end",
+            start,
+            end
+        ));
+    }
+    Ok(Range { start, end })
+}
+
+fn get_block_number(value: &str, height: Option<u64>) -> Result<u64> {
+    if !value.starts_with("latest") {
+        return Ok(value.parse::<u64>()?);
+    }
+    let height =
+        height.ok_or_else(|| anyhow!("Archive height is required to resolve '{}'", value))?;
+    match value.strip_prefix("latest") {
+        Some("") => Ok(height),
+        Some(offset) => {
+            let offset = offset
+                .strip_prefix('-')
+                .ok_or_else(|| anyhow!("Invalid block number: {}", value))?
+                .parse::<u64>()?;
+            Ok(height.saturating_sub(offset))
         }
-        let start = value[0].parse::<u64>()?;
-        let end = value[1].parse::<u64>()?;
-        Ok(Range { start, end })
+        None => Err(anyhow!("Invalid block number: {}", value)),
     }
 }
 
-//TODO make default range equal for archive height
-fn get_range(range: Option<String>) -> Result<Vec<String>> {
+//height is only fetched when the range refers to the head of the chain
+fn needs_height(range: &Option<String>) -> bool {
     match range {
-        Some(range) => {
-            if range.len() == 0 {
-                let default_range = vec!["1".to_owned(), "18000000".to_owned()];
-                Ok(default_range)
-            } else {
-                Ok(range.split(":").map(String::from).collect())
-            }
+        Some(range) => range.contains("latest") || range.split(':').any(|part| part.is_empty()),
+        None => true,
+    }
+}
+
+//returns the network name and the archive url to query it from
+fn get_network(network: Option<String>, archive_url: Option<String>) -> Result<(String, String)> {
+    match (network, archive_url) {
+        (network, Some(archive_url)) => {
+            let archive_url = archive_url.trim_end_matches('/').to_owned();
+            //name custom archives after the last url segment unless told otherwise
+            let network = match network {
+                Some(network) => network,
+                None => archive_url
+                    .rsplit('/')
+                    .next()
+                    .filter(|segment| !segment.is_empty() && !segment.contains(':'))
+                    .ok_or_else(|| anyhow!("Cannot infer network name from {}", archive_url))?
+                    .to_owned(),
+            };
+            Ok((network, archive_url))
         }
-        None => {
-            // let archive_height =
-            //     get_height("https://v2.archive.subsquid.io/network/ethereum-mainnet/height")
-            //         .await?;
-            let default_range = vec!["1".to_owned(), "18000000".to_owned()];
-            Ok(default_range)
+        (network, None) => {
+            let network = network.unwrap_or(DEFAULT_NETWORK.to_owned());
+            if network.is_empty() || network.contains('/') {
+                return Err(anyhow!("Invalid network: {}", network));
+            }
+            let archive_url = format!("{}/{}", ARCHIVE_URL, network);
+            Ok((network, archive_url))
         }
     }
 }
@@ -140,6 +556,7 @@ fn get_fields(fields: Option<Vec<String>>, dataset: Dataset) -> Result<Vec<Strin
                 "to".to_owned(),
                 "input".to_owned(),
                 "value".to_owned(),
+                "blockNumber".to_owned(),
             ]);
             if let Some(additional_fields) = fields {
                 additional_fields.into_iter().for_each(|field| {
@@ -157,6 +574,7 @@ fn get_fields(fields: Option<Vec<String>>, dataset: Dataset) -> Result<Vec<Strin
                 "address".to_owned(),
                 "data".to_owned(),
                 "topics".to_owned(),
+                "blockNumber".to_owned(),
             ]);
             if let Some(additional_fields) = fields {
                 additional_fields.into_iter().for_each(|field| {
@@ -165,8 +583,52 @@ fn get_fields(fields: Option<Vec<String>>, dataset: Dataset) -> Result<Vec<Strin
             }
             verify_log_fields(default_fields.into_iter().collect::<Vec<String>>())
         }
+        Dataset::Traces => {
+            let mut default_fields = HashSet::from([
+                "transactionIndex".to_owned(),
+                "traceAddress".to_owned(),
+                "type".to_owned(),
+                "callFrom".to_owned(),
+                "callTo".to_owned(),
+                "callValue".to_owned(),
+                "callSighash".to_owned(),
+                "error".to_owned(),
+            ]);
+            if let Some(additional_fields) = fields {
+                additional_fields.into_iter().for_each(|field| {
+                    default_fields.insert(field);
+                });
+            }
+            verify_trace_fields(default_fields.into_iter().collect::<Vec<String>>())
+        }
+        Dataset::StateDiffs => {
+            let mut default_fields = HashSet::from([
+                "transactionIndex".to_owned(),
+                "address".to_owned(),
+                "key".to_owned(),
+                "kind".to_owned(),
+                "prev".to_owned(),
+                "next".to_owned(),
+            ]);
+            if let Some(additional_fields) = fields {
+                additional_fields.into_iter().for_each(|field| {
+                    default_fields.insert(field);
+                });
+            }
+            verify_state_diff_fields(default_fields.into_iter().collect::<Vec<String>>())
+        }
+    }
+}
+//header field behind a block context column of transaction and log rows
+pub fn get_context_field(dataset: Dataset, field: &str) -> Option<&'static str> {
+    match (dataset, field) {
+        (Dataset::Transactions | Dataset::Logs, "blockNumber") => Some("number"),
+        (Dataset::Transactions | Dataset::Logs, "blockHash") => Some("hash"),
+        (Dataset::Transactions | Dataset::Logs, "timestamp") => Some("timestamp"),
+        _ => None,
     }
 }
+
 fn verify_transaction_fields(fields: Vec<String>) -> Result<Vec<String>> {
     let valid_fields: &[&str] = &[
         "id",
@@ -196,7 +658,6 @@ fn verify_transaction_fields(fields: Vec<String>) -> Result<Vec<String>> {
         "blockHash",
         "blockNumber",
         "timestamp",
-        "type",
     ];
 
     fields
@@ -249,6 +710,53 @@ fn verify_log_fields(fields: Vec<String>) -> Result<Vec<String>> {
         "address",
         "data",
         "topics",
+        "blockNumber",
+        "blockHash",
+        "timestamp",
+    ];
+
+    fields
+        .into_iter()
+        .map(|field| {
+            if valid_fields.contains(&field.as_str()) {
+                Ok(field)
+            } else {
+                Err(anyhow!("Invalid field: {}", field))
+            }
+        })
+        .collect()
+}
+
+fn verify_trace_fields(fields: Vec<String>) -> Result<Vec<String>> {
+    let valid_fields: &[&str] = &[
+        "transactionIndex",
+        "traceAddress",
+        "subtraces",
+        "type",
+        "error",
+        "revertReason",
+        "createFrom",
+        "createValue",
+        "createGas",
+        "createInit",
+        "createResultGasUsed",
+        "createResultCode",
+        "createResultAddress",
+        "callFrom",
+        "callTo",
+        "callValue",
+        "callGas",
+        "callInput",
+        "callSighash",
+        "callType",
+        "callResultGasUsed",
+        "callResultOutput",
+        "suicideAddress",
+        "suicideRefundAddress",
+        "suicideBalance",
+        "rewardAuthor",
+        "rewardValue",
+        "rewardType",
     ];
 
     fields
@@ -263,44 +771,334 @@ fn verify_log_fields(fields: Vec<String>) -> Result<Vec<String>> {
         .collect()
 }
 
-fn get_dataset(dataset: Option<String>) -> Result<Dataset> {
+fn verify_state_diff_fields(fields: Vec<String>) -> Result<Vec<String>> {
+    let valid_fields: &[&str] = &["transactionIndex", "address", "key", "kind", "prev", "next"];
+
+    fields
+        .into_iter()
+        .map(|field| {
+            if valid_fields.contains(&field.as_str()) {
+                Ok(field)
+            } else {
+                Err(anyhow!("Invalid field: {}", field))
+            }
+        })
+        .collect()
+}
+
+//`-d blocks,transactions,logs` fetches several datasets with one query
+fn get_datasets(dataset: Option<String>) -> Result<Vec<Dataset>> {
+    let dataset = dataset.ok_or_else(|| anyhow!("No dataset specified"))?;
+    let mut datasets = Vec::new();
+    for name in dataset.split(',') {
+        let dataset = Dataset::try_from(name.trim().to_owned())?;
+        if datasets.contains(&dataset) {
+            return Err(anyhow!("Duplicate dataset: {}", name));
+        }
+        datasets.push(dataset);
+    }
+    Ok(datasets)
+}
+
+//`--include-transactions` and `--include-logs` add the related rows as companion outputs
+fn get_related_datasets(
+    datasets: &[Dataset],
+    include_transactions: bool,
+    include_logs: bool,
+) -> Result<Vec<Dataset>> {
+    let mut related = Vec::new();
+    for (include, flag, source, target) in [
+        (
+            include_transactions,
+            "--include-transactions",
+            Dataset::Logs,
+            Dataset::Transactions,
+        ),
+        (
+            include_logs,
+            "--include-logs",
+            Dataset::Transactions,
+            Dataset::Logs,
+        ),
+    ] {
+        if !include {
+            continue;
+        }
+        if !datasets.contains(&source) {
+            return Err(anyhow!("{} needs the {} dataset", flag, source.get_name()));
+        }
+        if datasets.contains(&target) {
+            return Err(anyhow!(
+                "{} writes the {} itself, remove it from the datasets",
+                flag,
+                target.get_name()
+            ));
+        }
+        related.push(target);
+    }
+    Ok(related)
+}
+
+//fields and options of a multi-dataset run: `logs.address` goes to one dataset,
+//`address` to every dataset that has it
+fn split_by_dataset(
+    values: Option<Vec<String>>,
+    datasets: &[Dataset],
+    is_valid: fn(Dataset, &str) -> bool,
+) -> Result<Vec<Option<Vec<String>>>> {
+    let values = match values {
+        Some(values) if datasets.len() > 1 => values,
+        //a single dataset takes everything, invalid values are reported by its checks
+        values => return Ok(vec![values; datasets.len()]),
+    };
+    let mut split: Vec<Option<Vec<String>>> = vec![None; datasets.len()];
+    for value in values {
+        let qualified = value.split_once('.').and_then(|(name, rest)| {
+            let index = datasets.iter().position(|d| d.get_name() == name)?;
+            Some((index, rest))
+        });
+        let targets: Vec<(usize, &str)> = match qualified {
+            Some((index, rest)) => vec![(index, rest)],
+            None => datasets
+                .iter()
+                .enumerate()
+                .filter(|(_, dataset)| is_valid(**dataset, &value))
+                .map(|(index, _)| (index, value.as_str()))
+                .collect(),
+        };
+        if targets.is_empty() {
+            return Err(anyhow!("{} does not apply to any dataset", value));
+        }
+        for (index, value) in targets {
+            split[index]
+                .get_or_insert_with(Vec::new)
+                .push(value.to_owned());
+        }
+    }
+    Ok(split)
+}
+
+fn is_valid_field(dataset: Dataset, field: &str) -> bool {
+    verify_fields(dataset, vec![field.to_owned()]).is_ok()
+}
+
+fn is_valid_option(dataset: Dataset, option: &str) -> bool {
+    if option == OPTION_GROUP_SEPARATOR {
+        return dataset != Dataset::Blocks;
+    }
+    let key = option.split(':').next().unwrap_or_default();
+    dataset != Dataset::Blocks
+        && get_verified_options(dataset).is_some_and(|options| options.iter().any(|o| o == key))
+}
+
+fn verify_fields(dataset: Dataset, fields: Vec<String>) -> Result<Vec<String>> {
     match dataset {
-        Some(dataset) => match dataset.as_str() {
-            "blocks" => Ok(Dataset::Blocks),
-            "transactions" => Ok(Dataset::Transactions),
-            "logs" => Ok(Dataset::Logs),
-            _ => Err(anyhow!("Invalid dataset")),
+        Dataset::Blocks => verify_block_fields(fields),
+        Dataset::Transactions => verify_transaction_fields(fields),
+        Dataset::Logs => verify_log_fields(fields),
+        Dataset::Traces => verify_trace_fields(fields),
+        Dataset::StateDiffs => verify_state_diff_fields(fields),
+    }
+}
+
+fn get_format(format: Option<String>) -> Result<OutputFormat> {
+    match format.as_deref() {
+        None | Some("parquet") => Ok(OutputFormat::Parquet),
+        Some("csv") => Ok(OutputFormat::Csv),
+        Some("jsonl") => Ok(OutputFormat::JsonLines),
+        Some("arrow-ipc") => Ok(OutputFormat::ArrowIpc),
+        Some(format) => Err(anyhow!("Invalid format: {}", format)),
+    }
+}
+
+fn get_binary(binary: bool, format: OutputFormat) -> Result<bool> {
+    match format {
+        OutputFormat::Csv | OutputFormat::JsonLines if binary => Err(anyhow!(
+            "Binary columns only apply to parquet and arrow-ipc output"
+        )),
+        _ => Ok(binary),
+    }
+}
+
+fn get_u256_format(u256: Option<String>) -> Result<U256Format> {
+    match u256.as_deref() {
+        None | Some("string") => Ok(U256Format::String),
+        Some("binary") => Ok(U256Format::Binary),
+        Some("decimal") => Ok(U256Format::Decimal),
+        Some("hilo") => Ok(U256Format::HiLo),
+        Some(u256) => Err(anyhow!("Invalid u256 format: {}", u256)),
+    }
+}
+
+fn get_parquet_options(opts: &Opts, format: OutputFormat) -> Result<ParquetOptions> {
+    let is_set = opts.compression.is_some()
+        || opts.compression_level.is_some()
+        || opts.row_group_size.is_some()
+        || opts.statistics;
+    if is_set && format != OutputFormat::Parquet {
+        return Err(anyhow!(
+            "Compression, row group size and statistics only apply to parquet output"
+        ));
+    }
+    let level = opts.compression_level;
+    let compression = match (opts.compression.as_deref(), level) {
+        (None | Some("snappy"), None) => Compression::Snappy,
+        (Some("uncompressed"), None) => Compression::Uncompressed,
+        (Some("lz4"), None) => Compression::Lz4,
+        (Some("gzip"), None) => Compression::Gzip(None),
+        (Some("gzip"), Some(level @ 0..=10)) => Compression::Gzip(Some(level as u8)),
+        (Some("zstd"), None) => Compression::Zstd(None),
+        (Some("zstd"), Some(level @ 1..=22)) => Compression::Zstd(Some(level)),
+        (Some("gzip" | "zstd"), Some(level)) => {
+            return Err(anyhow!("Invalid compression level: {}", level))
+        }
+        (None | Some("snappy" | "uncompressed" | "lz4"), Some(_)) => {
+            return Err(anyhow!("Compression level requires gzip or zstd"))
+        }
+        (Some(compression), _) => return Err(anyhow!("Invalid compression: {}", compression)),
+    };
+    let row_group_size = match opts.row_group_size {
+        Some(0) => return Err(anyhow!("Row group size must be greater than 0")),
+        row_group_size => row_group_size,
+    };
+    Ok(ParquetOptions {
+        compression,
+        row_group_size,
+        statistics: opts.statistics,
+    })
+}
+
+fn get_memory_budget(memory_budget: Option<u64>, blocks_per_file: Option<u64>) -> Result<u64> {
+    //files are held until every block of them is fetched, the budget would not bound them
+    if let (Some(_), Some(_)) = (memory_budget, blocks_per_file) {
+        return Err(anyhow!(
+            "Memory budget cannot be combined with blocks per file, files are kept in memory until complete"
+        ));
+    }
+    match memory_budget.unwrap_or(DEFAULT_MEMORY_BUDGET) {
+        0 => Err(anyhow!("Memory budget must be greater than 0")),
+        megabytes => Ok(megabytes * 1024 * 1024),
+    }
+}
+
+fn get_request_timeout(request_timeout: Option<u64>) -> Result<Duration> {
+    match request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT) {
+        0 => Err(anyhow!("Request timeout must be greater than 0")),
+        seconds => Ok(Duration::from_secs(seconds)),
+    }
+}
+
+fn get_concurrency(
+    concurrency: Option<String>,
+    max_concurrency: Option<usize>,
+) -> Result<Concurrency> {
+    let max = max_concurrency.unwrap_or(DEFAULT_MAX_CONCURRENCY);
+    if max == 0 {
+        return Err(anyhow!("Max concurrency must be greater than 0"));
+    }
+    match concurrency.as_deref() {
+        None => Ok(Concurrency::Fixed(DEFAULT_CONCURRENCY)),
+        Some("auto") => Ok(Concurrency::Adaptive { max }),
+        Some(limit) => match limit.parse::<usize>().ok() {
+            Some(0) | None => Err(anyhow!("Invalid concurrency: {}", limit)),
+            Some(limit) => Ok(Concurrency::Fixed(limit)),
         },
-        None => Err(anyhow!("No dataset specified")),
     }
 }
 
-fn get_options(
-    options: Option<Vec<String>>,
-    dataset: Dataset,
-) -> Result<HashMap<String, Vec<String>>, anyhow::Error> {
-    match options {
-        Some(options) => {
-            if dataset == Dataset::Blocks || options.is_empty() {
-                return Ok(HashMap::new());
-            }
-            let verified_options = get_verified_options(dataset).unwrap();
-            let mut options_map: HashMap<String, Vec<String>> = HashMap::new();
-            for option in options {
-                let option_value = option.split(":").collect::<Vec<&str>>();
-                if verified_options.contains(&option_value[0].to_string()) {
-                    options_map.insert(
-                        option_value[0].to_string(),
-                        vec![option_value[1].to_string()], //add mult append
-                    );
-                } else {
-                    return Err(anyhow!("Invalid option"));
-                }
+fn get_retry_policy(
+    max_attempts: Option<u32>,
+    max_backoff: Option<u64>,
+    jitter: Option<u64>,
+) -> Result<RetryPolicy> {
+    let default = RetryPolicy::default();
+    let max_attempts = match max_attempts {
+        Some(0) => return Err(anyhow!("Max attempts must be greater than 0")),
+        max_attempts => max_attempts.unwrap_or(default.max_attempts),
+    };
+    Ok(RetryPolicy {
+        max_attempts,
+        max_backoff: max_backoff.map_or(default.max_backoff, Duration::from_millis),
+        jitter: jitter.map_or(default.jitter, Duration::from_millis),
+    })
+}
+
+fn get_blocks_per_file(blocks_per_file: Option<u64>) -> Result<Option<u64>> {
+    match blocks_per_file {
+        Some(0) => Err(anyhow!("Blocks per file must be greater than 0")),
+        blocks_per_file => Ok(blocks_per_file),
+    }
+}
+
+fn get_partition(partition: Option<String>, size: Option<u64>) -> Result<Option<Partition>> {
+    match partition.as_deref() {
+        None => Ok(None),
+        Some("block") => match size.unwrap_or(DEFAULT_PARTITION_SIZE) {
+            0 => Err(anyhow!("Partition size must be greater than 0")),
+            size => Ok(Some(Partition::BlockBucket(size))),
+        },
+        Some("date") => Ok(Some(Partition::Date)),
+        Some(partition) => Err(anyhow!("Invalid partition: {}", partition)),
+    }
+}
+
+//`-o address:0xa,0xb -o address:0xc` selects any of the three addresses,
+//`address:@contracts.txt` reads the values from a file and `or` starts another selection
+fn get_options(options: Option<Vec<String>>, dataset: Dataset) -> Result<Vec<Selection>> {
+    let options = match options {
+        Some(options) if dataset != Dataset::Blocks => options,
+        _ => return Ok(Vec::new()),
+    };
+    let verified_options = get_verified_options(dataset).unwrap();
+    let mut selections: Vec<Selection> = vec![HashMap::new()];
+    for option in options.iter().filter(|option| !option.is_empty()) {
+        if option == OPTION_GROUP_SEPARATOR {
+            selections.push(HashMap::new());
+            continue;
+        }
+        let (key, value) = option
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid option {}, expected key:value", option))?;
+        if !verified_options.iter().any(|verified| verified == key) {
+            return Err(anyhow!("Invalid option: {}", key));
+        }
+        let values = match value.strip_prefix('@') {
+            Some(path) => read_option_values(Path::new(path))?,
+            None => split_option_values(value),
+        };
+        if values.is_empty() {
+            return Err(anyhow!("No values given for option {}", key));
+        }
+        let selection = selections.last_mut().unwrap();
+        let entry = selection.entry(key.to_owned()).or_default();
+        for value in values {
+            if !entry.contains(&value) {
+                entry.push(value);
             }
-            Ok(options_map)
         }
-        None => Ok(HashMap::new()),
     }
+    //an empty selection would match every row
+    selections.retain(|selection| !selection.is_empty());
+    Ok(selections)
+}
+
+fn split_option_values(values: &str) -> Vec<String> {
+    values
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_owned())
+        .collect()
+}
+
+//one or more values per line, `#` starts a comment
+fn read_option_values(path: &Path) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Cannot read option values from {}: {}", path.display(), e))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(split_option_values)
+        .collect())
 }
 
 fn get_verified_options(dataset: Dataset) -> Option<Vec<String>> {
@@ -318,6 +1116,20 @@ fn get_verified_options(dataset: Dataset) -> Option<Vec<String>> {
             "topic2".to_string(),
             "topic3".to_string(),
         ]),
+        Dataset::Traces => Some(vec![
+            "type".to_string(),
+            "createFrom".to_string(),
+            "callFrom".to_string(),
+            "callTo".to_string(),
+            "callSighash".to_string(),
+            "suicideRefundAddress".to_string(),
+            "rewardAuthor".to_string(),
+        ]),
+        Dataset::StateDiffs => Some(vec![
+            "address".to_string(),
+            "key".to_string(),
+            "kind".to_string(),
+        ]),
         //_ => None,
     }
 }
@@ -325,7 +1137,7 @@ fn get_verified_options(dataset: Dataset) -> Option<Vec<String>> {
 #[cfg(test)]
 mod tests {
 
-    use super::{Config, Dataset, Range};
+    use super::{get_options, get_range, Compression, Config, Dataset, Range};
     use crate::cli::opts::Opts;
     use anyhow::Result;
 
@@ -336,6 +1148,7 @@ mod tests {
             range: Some("1:10".to_owned()),
             fields: Some(vec!["timestamp".to_owned()]),
             options: Some(vec!["".to_owned()]),
+            ..Default::default()
         }
         .try_into()?;
         assert_eq!(opts.dataset, Dataset::Blocks);
@@ -356,6 +1169,7 @@ mod tests {
                 "logsBloom".to_owned(),
             ]),
             options: Some(vec!["".to_owned()]),
+            ..Default::default()
         }
         .try_into()?;
         assert_eq!(opts.dataset, Dataset::Blocks);
@@ -378,12 +1192,111 @@ mod tests {
         return Ok(());
     }
     #[test]
+    fn test_network() -> Result<()> {
+        let config: Config = Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("1:10".to_owned()),
+            network: Some("base-mainnet".to_owned()),
+            ..Default::default()
+        }
+        .try_into()?;
+        assert_eq!(config.network, "base-mainnet");
+        assert_eq!(
+            config.archive_url,
+            "https://v2.archive.subsquid.io/network/base-mainnet"
+        );
+
+        let config: Config = Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("1:10".to_owned()),
+            archive_url: Some("http://localhost:8000/network/my-devnet/".to_owned()),
+            ..Default::default()
+        }
+        .try_into()?;
+        assert_eq!(config.network, "my-devnet");
+        assert_eq!(
+            config.archive_url,
+            "http://localhost:8000/network/my-devnet"
+        );
+
+        Ok(())
+    }
+    #[test]
+    fn test_range() -> Result<()> {
+        assert_eq!(
+            get_range(Some("latest-100:latest".to_owned()), Some(1000))?,
+            Range {
+                start: 900,
+                end: 1001
+            }
+        );
+        //the last 100 blocks, stopping 10 blocks before the head
+        assert_eq!(
+            get_range(Some("latest-109:latest-10".to_owned()), Some(1000))?,
+            Range {
+                start: 891,
+                end: 991
+            }
+        );
+        assert_eq!(
+            get_range(Some("500:latest-10".to_owned()), Some(1000))?,
+            Range {
+                start: 500,
+                end: 991
+            }
+        );
+        assert_eq!(
+            get_range(Some("17:".to_owned()), Some(20))?,
+            Range { start: 17, end: 21 }
+        );
+        assert_eq!(get_range(None, Some(20))?, Range { start: 1, end: 21 });
+        assert!(get_range(Some("17:".to_owned()), None).is_err());
+        assert!(get_range(Some("20:10".to_owned()), None).is_err());
+
+        Ok(())
+    }
+    #[test]
+    fn test_memory_budget() -> Result<()> {
+        let opts = |memory_budget: Option<u64>, blocks_per_file: Option<u64>| Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("1:10".to_owned()),
+            memory_budget,
+            blocks_per_file,
+            ..Default::default()
+        };
+        let config: Config = opts(Some(16), None).try_into()?;
+        assert_eq!(config.memory_budget, 16 * 1024 * 1024);
+        assert!(Config::try_from(opts(Some(0), None)).is_err());
+        assert!(Config::try_from(opts(Some(16), Some(1000))).is_err());
+        let config: Config = opts(None, Some(1000)).try_into()?;
+        assert_eq!(config.blocks_per_file, Some(1000));
+        Ok(())
+    }
+    #[test]
+    fn test_compression() -> Result<()> {
+        let opts = |compression: &str, level: Option<i32>| Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("1:10".to_owned()),
+            compression: Some(compression.to_owned()),
+            compression_level: level,
+            ..Default::default()
+        };
+        let config: Config = opts("zstd", Some(19)).try_into()?;
+        assert_eq!(config.parquet.compression, Compression::Zstd(Some(19)));
+        assert!(Config::try_from(opts("zstd", Some(23))).is_err());
+        assert!(Config::try_from(opts("lz4", Some(3))).is_err());
+        assert!(Config::try_from(opts("brotli", None)).is_err());
+
+        Ok(())
+    }
+    #[test]
     fn test_transaction_fields() -> Result<()> {
         let opts: Config = Opts {
             dataset: Some("transactions".to_owned()),
             range: Some("1:10000".to_owned()),
             fields: Some(vec!["id".to_owned(), "from".to_owned(), "to".to_owned()]),
             options: Some(vec!["".to_owned()]),
+            ..Default::default()
         }
         .try_into()?;
         print!("{:?}", opts);
@@ -401,6 +1314,109 @@ mod tests {
         );
         return Ok(());
     }
+    #[test]
+    fn test_multiple_datasets() -> Result<()> {
+        let config: Config = Opts {
+            dataset: Some("blocks,transactions,logs".to_owned()),
+            range: Some("1:10".to_owned()),
+            fields: Some(vec!["hash".to_owned(), "logs.data".to_owned()]),
+            options: Some(vec![
+                "address:0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48".to_owned()
+            ]),
+            ..Default::default()
+        }
+        .try_into()?;
+        assert_eq!(config.get_datasets_name(), "blocks,transactions,logs");
+        let [blocks, transactions, logs] = &config.datasets[..] else {
+            panic!("expected three datasets");
+        };
+        assert!(blocks.fields.contains(&"hash".to_owned()));
+        assert!(transactions.fields.contains(&"hash".to_owned()));
+        assert!(logs.fields.contains(&"data".to_owned()));
+        assert!(!logs.fields.contains(&"hash".to_owned()));
+        assert!(transactions.options.is_empty());
+        assert!(logs.options[0].contains_key("address"));
+
+        let duplicate: Result<Config> = Opts {
+            dataset: Some("logs,logs".to_owned()),
+            ..Default::default()
+        }
+        .try_into();
+        assert!(duplicate.is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_options() -> Result<()> {
+        let path = std::env::temp_dir().join("little_squid_test_options.txt");
+        std::fs::write(&path, "0xc # third\n\n0xd,0xa\n")?;
+        let options = get_options(
+            Some(vec![
+                "address:0xa,0xb".to_owned(),
+                format!("address:@{}", path.display()),
+                "topic0:0x1".to_owned(),
+            ]),
+            Dataset::Logs,
+        )?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(options.len(), 1);
+        assert_eq!(options[0]["address"], vec!["0xa", "0xb", "0xc", "0xd"]);
+        assert_eq!(options[0]["topic0"], vec!["0x1"]);
+
+        //transfers from one token or swaps of one pool
+        let options = get_options(
+            Some(
+                [
+                    "address:0xa",
+                    "topic0:0xt",
+                    "or",
+                    "address:0xb",
+                    "topic0:0xs",
+                    "or",
+                ]
+                .map(String::from)
+                .to_vec(),
+            ),
+            Dataset::Logs,
+        )?;
+        assert_eq!(options.len(), 2);
+        assert_eq!(options[1]["address"], vec!["0xb"]);
+        assert_eq!(options[1]["topic0"], vec!["0xs"]);
+
+        assert!(get_options(Some(vec!["address".to_owned()]), Dataset::Logs).is_err());
+        assert!(get_options(Some(vec!["sighash:0x1".to_owned()]), Dataset::Logs).is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_related_datasets() -> Result<()> {
+        let config: Config = Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("1:10".to_owned()),
+            fields: Some(vec!["address".to_owned(), "transactions.from".to_owned()]),
+            options: Some(vec!["address:0xa".to_owned()]),
+            include_transactions: true,
+            ..Default::default()
+        }
+        .try_into()?;
+        let [logs, transactions] = &config.datasets[..] else {
+            panic!("expected logs and their transactions");
+        };
+        assert!(!logs.related && transactions.related);
+        assert_eq!(transactions.dataset, Dataset::Transactions);
+        assert!(transactions.fields.contains(&"from".to_owned()));
+        assert!(transactions.options.is_empty());
+
+        for (dataset, include_transactions) in [("blocks", true), ("logs,transactions", true)] {
+            let config: Result<Config> = Opts {
+                dataset: Some(dataset.to_owned()),
+                range: Some("1:10".to_owned()),
+                include_transactions,
+                ..Default::default()
+            }
+            .try_into();
+            assert!(config.is_err());
+        }
+        Ok(())
+    }
 }
 
 // #[test]
@@ -1,6 +1,6 @@
 use clap::Parser;
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Default)]
 #[clap()]
 pub struct Opts {
     //#[clap(short = 'a', long = "args")]
@@ -13,4 +13,73 @@ pub struct Opts {
     pub fields: Option<Vec<String>>,
     #[clap(short = 'o', long = "options", num_args(0..))]
     pub options: Option<Vec<String>>,
+    #[clap(short = 'n', long = "network")]
+    pub network: Option<String>,
+    #[clap(long = "archive-url")]
+    pub archive_url: Option<String>,
+    #[clap(long = "follow")]
+    pub follow: bool,
+    #[clap(long = "poll-interval")]
+    pub poll_interval: Option<u64>,
+    #[clap(long = "confirmations")]
+    pub confirmations: Option<u64>,
+    #[clap(long = "format")]
+    pub format: Option<String>,
+    #[clap(long = "output-dir")]
+    pub output_dir: Option<String>,
+    #[clap(long = "file-template")]
+    pub file_template: Option<String>,
+    #[clap(long = "partition")]
+    pub partition: Option<String>,
+    #[clap(long = "partition-size")]
+    pub partition_size: Option<u64>,
+    #[clap(long = "blocks-per-file")]
+    pub blocks_per_file: Option<u64>,
+    #[clap(long = "compression")]
+    pub compression: Option<String>,
+    #[clap(long = "compression-level")]
+    pub compression_level: Option<i32>,
+    #[clap(long = "row-group-size")]
+    pub row_group_size: Option<usize>,
+    #[clap(long = "statistics")]
+    pub statistics: bool,
+    //string, binary, decimal or hilo
+    #[clap(long = "u256")]
+    pub u256: Option<String>,
+    //hashes, addresses and calldata as binary columns
+    #[clap(long = "binary")]
+    pub binary: bool,
+    //parent transactions of the selected logs as a transactions output
+    #[clap(long = "include-transactions")]
+    pub include_transactions: bool,
+    //logs of the selected transactions as a logs output
+    #[clap(long = "include-logs")]
+    pub include_logs: bool,
+    //e.g. "Transfer(address indexed from, address indexed to, uint256 value)", repeatable
+    #[clap(long = "event")]
+    pub events: Option<Vec<String>>,
+    //JSON ABI whose events are decoded
+    #[clap(long = "abi")]
+    pub abi: Option<String>,
+    #[clap(long = "retry-failed")]
+    pub retry_failed: Option<String>,
+    #[clap(long = "max-attempts")]
+    pub max_attempts: Option<u32>,
+    //milliseconds
+    #[clap(long = "max-backoff")]
+    pub max_backoff: Option<u64>,
+    //milliseconds
+    #[clap(long = "jitter")]
+    pub jitter: Option<u64>,
+    //seconds before a request to the archive is given up
+    #[clap(long = "request-timeout")]
+    pub request_timeout: Option<u64>,
+    //a number, or `auto` to adapt to the archive
+    #[clap(long = "concurrency")]
+    pub concurrency: Option<String>,
+    #[clap(long = "max-concurrency")]
+    pub max_concurrency: Option<usize>,
+    //megabytes of fetched blocks held in memory
+    #[clap(long = "memory-budget")]
+    pub memory_budget: Option<u64>,
 }
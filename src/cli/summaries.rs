@@ -1,31 +1,125 @@
 use std::collections::HashSet;
+use std::path::Path;
 
-use crate::cli::config::Dataset;
+use crate::cli::config::{Config, Dataset, OutputFormat, U256Format};
+use crate::report::FailedRange;
 use colored::Colorize;
 
-use super::config::Range;
-
-pub fn print_intro(dataset: Dataset, fields: &[String], range: &Range, options: &Vec<&String>) {
+pub fn print_intro(config: &Config) {
+    let range = &config.range;
     print_header("'\nConfiguration");
-    print_bullet_indent("Dataset", get_dataset_string(dataset), 2);
-    print_bullet_indent("Fields", &fields.join(", "), 2);
-    print_bullet_indent("Range", &format!("{:?}:{:?}", range.start, range.end), 2);
-    let comma_separated: String = options
-        .iter()
-        .map(|s| s.as_str())
-        .collect::<Vec<&str>>()
-        .join(", ");
-    print_bullet_indent("Options", comma_separated, 2);
+    print_bullet_indent("Network", &config.network, 2);
+    for dataset in &config.datasets {
+        let mut name = get_dataset_string(dataset.dataset);
+        if dataset.related {
+            name.push_str(" (included)");
+        }
+        print_bullet_indent("Dataset", name, 2);
+        print_bullet_indent("Fields", dataset.fields.join(", "), 4);
+        let selections: String = dataset
+            .options
+            .iter()
+            .map(|selection| {
+                selection
+                    .values()
+                    .flatten()
+                    .map(|s| s.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            })
+            .collect::<Vec<String>>()
+            .join(" or ");
+        print_bullet_indent("Options", selections, 4);
+    }
+    if !config.events.is_empty() {
+        let events: Vec<&str> = config.events.iter().map(|e| e.table.as_str()).collect();
+        print_bullet_indent("Events", events.join(", "), 2);
+    }
+    print_bullet_indent("Range", format!("{:?}:{:?}", range.start, range.end), 2);
+    if let Some(retry_ranges) = &config.retry_ranges {
+        print_bullet_indent(
+            "Retrying",
+            format!("{} failed ranges", retry_ranges.len()),
+            2,
+        );
+    }
+    print_bullet_indent("Format", config.format.get_name(), 2);
+    print_bullet_indent("U256", config.u256.get_name(), 2);
+    if config.binary {
+        print_bullet_indent("Hex columns", "binary", 2);
+    }
+    if config.format == OutputFormat::Parquet {
+        print_bullet_indent("Compression", config.parquet.compression.get_name(), 2);
+    }
+    if let Some(partition) = config.partition {
+        print_bullet_indent("Partition", partition.get_name(), 2);
+    }
+    if let Some(blocks_per_file) = config.blocks_per_file {
+        print_bullet_indent("Blocks per file", blocks_per_file.to_string(), 2);
+    }
+    print_bullet_indent(
+        "Output",
+        config
+            .output_dir
+            .join(&config.file_template)
+            .to_string_lossy(),
+        2,
+    );
+    print_bullet_indent("Concurrency", config.concurrency.get_name(), 2);
+    if config.follow {
+        print_bullet_indent(
+            "Follow",
+            format!(
+                "every {}s, {} confirmations",
+                config.poll_interval.as_secs(),
+                config.confirmations
+            ),
+            2,
+        );
+    }
 
-    print_schema(dataset, fields);
+    for dataset in &config.datasets {
+        print_schema(dataset.dataset, &dataset.fields, config.u256);
+    }
 
     println!();
 }
 
-fn print_schema(dataset: Dataset, fields: &[String]) {
-    print_header("\nSchema");
+pub fn print_summary(config: &Config, failed: &[FailedRange], report_path: &Path) {
+    let total_blocks: u64 = config.get_ranges().iter().map(|r| r.end - r.start).sum();
+    let failed_blocks: u64 = failed.iter().map(|f| f.end - f.start).sum();
+    print_header("\nSummary");
+    print_bullet_indent(
+        "Blocks",
+        format!(
+            "{} of {}",
+            total_blocks.saturating_sub(failed_blocks),
+            total_blocks
+        ),
+        2,
+    );
+    if failed.is_empty() {
+        return;
+    }
+    print_bullet_indent("Failed ranges", failed.len().to_string(), 2);
+    for failure in failed {
+        print_bullet_indent(
+            format!("{}-{}", failure.start, failure.end - 1),
+            &failure.error,
+            4,
+        );
+    }
+    print_bullet_indent("Report", report_path.to_string_lossy(), 2);
+    println!(
+        "Rerun with --retry-failed {} to fetch them again",
+        report_path.to_string_lossy()
+    );
+}
+
+fn print_schema(dataset: Dataset, fields: &[String], u256: U256Format) {
+    print_header(format!("\nSchema: {}", dataset.get_name()));
     for field in fields {
-        let field_type = get_field_type(dataset, field);
+        let field_type = get_field_type(dataset, field, u256);
         print_bullet_indent(field, field_type, 2);
     }
     //let string_slice: &[String] = &fields;
@@ -36,11 +130,11 @@ fn print_schema(dataset: Dataset, fields: &[String]) {
     //get_other_available_fields(dataset, str_slice);
 }
 
-fn get_field_type(dataset: Dataset, field: &String) -> &str {
+fn get_field_type(dataset: Dataset, field: &str, u256: U256Format) -> &'static str {
     match dataset {
-        Dataset::Blocks => match field.as_str() {
+        Dataset::Blocks => match field {
             "blockHash" => "string",
-            "baseFeePerGas" => "number",
+            "baseFeePerGas" => u256.get_type(),
             "blockNumber" => "number",
             "miner" => "string",
             "parentHash" => "string",
@@ -52,7 +146,7 @@ fn get_field_type(dataset: Dataset, field: &String) -> &str {
             "gasUsed" => "number",
             "gasLimit" => "number",
             "difficulty" => "number",
-            "totalDifficulty" => "number",
+            "totalDifficulty" => u256.get_type(),
             "size" => "number",
             "extraData" => "string",
             "logsBloom" => "string",
@@ -64,24 +158,24 @@ fn get_field_type(dataset: Dataset, field: &String) -> &str {
             "number" => "number",
             _ => "unknown",
         },
-        Dataset::Transactions => match field.as_str() {
+        Dataset::Transactions => match field {
             "blockHash" => "string",
             "blockNumber" => "number",
             "from" => "string",
             "gas" => "number",
-            "gasPrice" => "number",
+            "gasPrice" => u256.get_type(),
             "hash" => "string",
             "input" => "string",
             "nonce" => "number",
             "to" => "string",
             "transactionIndex" => "number",
-            "value" => "number",
-            "v" => "string",
+            "value" => u256.get_type(),
+            "v" => "number",
             "r" => "string",
             "s" => "string",
             _ => "unknown",
         },
-        Dataset::Logs => match field.as_str() {
+        Dataset::Logs => match field {
             "address" => "string",
             "blockHash" => "string",
             "blockNumber" => "number",
@@ -93,6 +187,29 @@ fn get_field_type(dataset: Dataset, field: &String) -> &str {
             "transactionIndex" => "number",
             _ => "unknown",
         },
+        Dataset::Traces => match field {
+            "transactionIndex" => "number",
+            "traceAddress" => "array",
+            "subtraces" => "number",
+            "createGas" => "number",
+            "createResultGasUsed" => "number",
+            "callGas" => "number",
+            "callResultGasUsed" => "number",
+            "type" | "error" | "revertReason" => "string",
+            "createFrom" | "createInit" => "string",
+            "createResultCode" | "createResultAddress" => "string",
+            "callFrom" | "callTo" | "callInput" => "string",
+            "callSighash" | "callType" | "callResultOutput" => "string",
+            "suicideAddress" | "suicideRefundAddress" => "string",
+            "rewardAuthor" | "rewardType" => "string",
+            "createValue" | "callValue" | "suicideBalance" | "rewardValue" => u256.get_type(),
+            _ => "unknown",
+        },
+        Dataset::StateDiffs => match field {
+            "transactionIndex" => "number",
+            "address" | "key" | "kind" | "prev" | "next" => "string",
+            _ => "unknown",
+        },
     }
 }
 
@@ -108,6 +225,8 @@ fn get_dataset_string(dataset: Dataset) -> String {
         Dataset::Blocks => "Blocks".to_string(),
         Dataset::Transactions => "Transactions".to_string(),
         Dataset::Logs => "Logs".to_string(),
+        Dataset::Traces => "Traces".to_string(),
+        Dataset::StateDiffs => "State diffs".to_string(),
     }
 }
 
@@ -164,6 +283,37 @@ fn _get_other_available_fields(dataset: Dataset, fields: &[&str]) {
             "topics",
             "transactionHash",
         ],
+        Dataset::Traces => vec![
+            "transactionIndex",
+            "traceAddress",
+            "subtraces",
+            "type",
+            "error",
+            "revertReason",
+            "createFrom",
+            "createValue",
+            "createGas",
+            "createInit",
+            "createResultGasUsed",
+            "createResultCode",
+            "createResultAddress",
+            "callFrom",
+            "callTo",
+            "callValue",
+            "callGas",
+            "callInput",
+            "callSighash",
+            "callType",
+            "callResultGasUsed",
+            "callResultOutput",
+            "suicideAddress",
+            "suicideRefundAddress",
+            "suicideBalance",
+            "rewardAuthor",
+            "rewardValue",
+            "rewardType",
+        ],
+        Dataset::StateDiffs => vec!["transactionIndex", "address", "key", "kind", "prev", "next"],
     };
 
     _print_other_available_fields(&available_fields, fields);
@@ -0,0 +1,529 @@
+use crate::cli::config::U256Format;
+use crate::export::u256::create_u256_columns;
+use crate::models::{Block, Log, Scalar};
+use anyhow::{anyhow, Result};
+use ethnum::{I256, U256};
+use polars::prelude::{DataFrame, NamedFrom, Series};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tiny_keccak::{Hasher, Keccak};
+
+//columns every event table starts with
+const LOG_COLUMNS: [&str; 5] = [
+    "blockNumber",
+    "transactionIndex",
+    "logIndex",
+    "transactionHash",
+    "address",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ParamType {
+    Address,
+    Bool,
+    Uint(usize),
+    Int(usize),
+    FixedBytes(usize),
+    Bytes,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Param {
+    pub name: String,
+    pub kind: ParamType,
+    pub indexed: bool,
+}
+
+/// An event to decode from the logs, written to a table of its own.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    //name of the output table, the event name unless it is overloaded
+    pub table: String,
+    pub name: String,
+    pub params: Vec<Param>,
+    //0x-prefixed keccak hash of the canonical signature
+    pub topic0: String,
+}
+
+//an event of an ABI JSON file
+#[derive(Deserialize)]
+struct AbiItem {
+    #[serde(rename = "type")]
+    item_type: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    inputs: Vec<AbiParam>,
+    #[serde(default)]
+    anonymous: bool,
+}
+
+#[derive(Deserialize)]
+struct AbiParam {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    indexed: bool,
+}
+
+//a decoded parameter value
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Bool(bool),
+    Uint(U256),
+    Int(I256),
+    Bytes(Vec<u8>),
+    String(String),
+}
+
+impl ParamType {
+    pub fn parse(kind: &str) -> Result<ParamType> {
+        let bits = |bits: &str, max: usize, step: usize| match bits.parse::<usize>() {
+            Ok(bits) if bits > 0 && bits <= max && bits % step == 0 => Ok(bits),
+            _ => Err(anyhow!("Invalid parameter type {}", kind)),
+        };
+        match kind {
+            "address" => Ok(ParamType::Address),
+            "bool" => Ok(ParamType::Bool),
+            "bytes" => Ok(ParamType::Bytes),
+            "string" => Ok(ParamType::String),
+            "uint" => Ok(ParamType::Uint(256)),
+            "int" => Ok(ParamType::Int(256)),
+            _ if kind.starts_with("uint") => Ok(ParamType::Uint(bits(&kind[4..], 256, 8)?)),
+            _ if kind.starts_with("int") => Ok(ParamType::Int(bits(&kind[3..], 256, 8)?)),
+            _ if kind.starts_with("bytes") => Ok(ParamType::FixedBytes(bits(&kind[5..], 32, 1)?)),
+            //arrays and tuples are not decoded
+            _ => Err(anyhow!("Unsupported parameter type {}", kind)),
+        }
+    }
+
+    fn get_name(&self) -> String {
+        match self {
+            ParamType::Address => "address".to_owned(),
+            ParamType::Bool => "bool".to_owned(),
+            ParamType::Uint(bits) => format!("uint{}", bits),
+            ParamType::Int(bits) => format!("int{}", bits),
+            ParamType::FixedBytes(size) => format!("bytes{}", size),
+            ParamType::Bytes => "bytes".to_owned(),
+            ParamType::String => "string".to_owned(),
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        matches!(self, ParamType::Bytes | ParamType::String)
+    }
+}
+
+impl Event {
+    fn new(name: &str, params: Vec<Param>) -> Event {
+        let types: Vec<String> = params.iter().map(|param| param.kind.get_name()).collect();
+        let signature = format!("{}({})", name, types.join(","));
+        let mut hash = [0u8; 32];
+        let mut keccak = Keccak::v256();
+        keccak.update(signature.as_bytes());
+        keccak.finalize(&mut hash);
+        Event {
+            table: name.to_owned(),
+            name: name.to_owned(),
+            params,
+            topic0: format!("0x{}", hex::encode(hash)),
+        }
+    }
+
+    //e.g. `Transfer(address indexed from, address indexed to, uint256 value)`
+    pub fn parse(signature: &str) -> Result<Event> {
+        let signature = signature.trim();
+        let signature = signature.strip_prefix("event ").unwrap_or(signature).trim();
+        let (name, params) = signature
+            .strip_suffix(')')
+            .and_then(|signature| signature.split_once('('))
+            .ok_or_else(|| anyhow!("Invalid event signature {}", signature))?;
+        let params = params
+            .split(',')
+            .map(str::trim)
+            .filter(|param| !param.is_empty())
+            .enumerate()
+            .map(|(index, param)| {
+                let mut words = param.split_whitespace();
+                let kind = ParamType::parse(words.next().unwrap_or_default())?;
+                let mut indexed = false;
+                let mut name = None;
+                for word in words {
+                    match word {
+                        "indexed" if !indexed && name.is_none() => indexed = true,
+                        _ if name.is_none() => name = Some(word.to_owned()),
+                        _ => return Err(anyhow!("Invalid event parameter {}", param)),
+                    }
+                }
+                Ok(Param {
+                    name: get_param_name(name, index),
+                    kind,
+                    indexed,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Event::new(name.trim(), params))
+    }
+
+    fn from_abi(item: &AbiItem) -> Result<Event> {
+        let params = item
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                Ok(Param {
+                    name: get_param_name(Some(input.name.clone()), index),
+                    kind: ParamType::parse(&input.kind)?,
+                    indexed: input.indexed,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Event::new(&item.name, params))
+    }
+
+    fn decode(&self, log: &Log) -> Result<Vec<Token>> {
+        let topics = log.topics.as_deref().unwrap_or_default();
+        let data = log
+            .data
+            .as_ref()
+            .and_then(Scalar::as_str)
+            .map(|data| hex::decode(data.trim_start_matches("0x")))
+            .transpose()?
+            .unwrap_or_default();
+        let mut topic_index = 1;
+        let mut data_index = 0;
+        self.params
+            .iter()
+            .map(|param| {
+                if param.indexed {
+                    let topic = hex::decode(topics[topic_index].trim_start_matches("0x"))?;
+                    topic_index += 1;
+                    let word = get_word(&topic, 0)?;
+                    //indexed strings and bytes are only stored as their hash
+                    if param.kind.is_dynamic() {
+                        return Ok(Token::Bytes(word.to_vec()));
+                    }
+                    return decode_word(param.kind, word);
+                }
+                let word = get_word(&data, data_index * 32)?;
+                data_index += 1;
+                if !param.kind.is_dynamic() {
+                    return decode_word(param.kind, word);
+                }
+                let offset = get_offset(word)?;
+                let length = get_offset(get_word(&data, offset)?)?;
+                let bytes = data
+                    .get(offset + 32..)
+                    .and_then(|bytes| bytes.get(..length))
+                    .ok_or_else(|| anyhow!("Data is too short for {}", param.name))?;
+                Ok(match param.kind {
+                    ParamType::String => Token::String(String::from_utf8_lossy(bytes).into_owned()),
+                    _ => Token::Bytes(bytes.to_vec()),
+                })
+            })
+            .collect()
+    }
+
+    //the ERC-20 and ERC-721 `Transfer` share topic0, the number of topics tells them apart
+    fn matches(&self, log: &Log) -> bool {
+        let topics = log.topics.as_deref().unwrap_or_default();
+        let indexed = self.params.iter().filter(|param| param.indexed).count();
+        topics.len() == indexed + 1
+            && topics
+                .first()
+                .is_some_and(|topic| topic.eq_ignore_ascii_case(&self.topic0))
+    }
+}
+
+//`--event` signatures and the events of an `--abi` file
+pub fn get_events(signatures: Option<Vec<String>>, abi: Option<String>) -> Result<Vec<Event>> {
+    let mut events = signatures
+        .unwrap_or_default()
+        .iter()
+        .map(|signature| Event::parse(signature))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(path) = abi {
+        events.extend(load_abi(Path::new(&path))?);
+    }
+    //overloaded events get numbered tables, `Transfer` and `Transfer_2`
+    for index in 0..events.len() {
+        let (previous, rest) = events.split_at_mut(index);
+        let event = &mut rest[0];
+        if previous
+            .iter()
+            .any(|other| other.topic0 == event.topic0 && other.params == event.params)
+        {
+            return Err(anyhow!("Event {} is given twice", event.name));
+        }
+        let count = previous
+            .iter()
+            .filter(|other| other.name == event.name)
+            .count();
+        if count > 0 {
+            event.table = format!("{}_{}", event.name, count + 1);
+        }
+    }
+    Ok(events)
+}
+
+//a plain ABI array or a build artifact with an `abi` key
+fn load_abi(path: &Path) -> Result<Vec<Event>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Cannot read ABI {}: {}", path.display(), e))?;
+    let mut json: serde_json::Value = serde_json::from_str(&contents)?;
+    if let Some(abi) = json.get_mut("abi") {
+        json = abi.take();
+    }
+    let items: Vec<AbiItem> = serde_json::from_value(json)
+        .map_err(|e| anyhow!("Invalid ABI {}: {}", path.display(), e))?;
+    let mut events = Vec::new();
+    //anonymous events have no topic0 to find them by
+    for item in items
+        .iter()
+        .filter(|item| item.item_type == "event" && !item.anonymous)
+    {
+        match Event::from_abi(item) {
+            Ok(event) => events.push(event),
+            Err(e) => eprintln!("Skipping event {} of the ABI: {}", item.name, e),
+        }
+    }
+    Ok(events)
+}
+
+//one row per matching log, undecodable logs are skipped
+pub fn decode_events(
+    event: &Event,
+    blocks: &[&Block],
+    u256: U256Format,
+    binary: bool,
+) -> Result<DataFrame> {
+    let mut block_numbers = Vec::new();
+    let mut transaction_indexes = Vec::new();
+    let mut log_indexes = Vec::new();
+    let mut transaction_hashes = Vec::new();
+    let mut addresses = Vec::new();
+    let mut rows = Vec::new();
+    let mut failures = 0;
+    for block in blocks {
+        for log in block.logs.iter().filter(|log| event.matches(log)) {
+            match event.decode(log) {
+                Ok(row) => rows.push(row),
+                Err(_) => {
+                    failures += 1;
+                    continue;
+                }
+            }
+            block_numbers.push(block.header.number);
+            transaction_indexes.push(log.transaction_index.as_ref().and_then(Scalar::as_u64));
+            log_indexes.push(log.log_index.as_ref().and_then(Scalar::as_u64));
+            let bytes = |value: &Option<Scalar>| {
+                value
+                    .as_ref()
+                    .and_then(Scalar::as_str)
+                    .and_then(|value| hex::decode(value.trim_start_matches("0x")).ok())
+                    .unwrap_or_default()
+            };
+            transaction_hashes.push(Token::Bytes(bytes(&log.transaction_hash)));
+            addresses.push(Token::Bytes(bytes(&log.address)));
+        }
+    }
+    if failures > 0 {
+        eprintln!("{} {} logs could not be decoded", failures, event.name);
+    }
+
+    let mut columns = vec![
+        Series::new(LOG_COLUMNS[0], block_numbers),
+        Series::new(LOG_COLUMNS[1], transaction_indexes),
+        Series::new(LOG_COLUMNS[2], log_indexes),
+        create_bytes_column(LOG_COLUMNS[3], transaction_hashes, binary),
+        create_bytes_column(LOG_COLUMNS[4], addresses, binary),
+    ];
+    for (index, param) in event.params.iter().enumerate() {
+        let values: Vec<Token> = rows.iter().map(|row| row[index].clone()).collect();
+        //parameters named like a log column are prefixed
+        let name = if LOG_COLUMNS.contains(&param.name.as_str()) {
+            format!("arg_{}", param.name)
+        } else {
+            param.name.clone()
+        };
+        columns.extend(create_param_columns(&name, param, values, u256, binary));
+    }
+    Ok(DataFrame::new(columns)?)
+}
+
+fn create_param_columns(
+    name: &str,
+    param: &Param,
+    values: Vec<Token>,
+    u256: U256Format,
+    binary: bool,
+) -> Vec<Series> {
+    let kind = match (param.indexed, param.kind) {
+        (true, kind) if kind.is_dynamic() => ParamType::FixedBytes(32),
+        (_, kind) => kind,
+    };
+    match kind {
+        ParamType::Bool => {
+            let values: Vec<bool> = values
+                .into_iter()
+                .map(|value| value == Token::Bool(true))
+                .collect();
+            vec![Series::new(name, values)]
+        }
+        ParamType::Uint(bits) => {
+            let values: Vec<U256> = values
+                .into_iter()
+                .filter_map(|value| match value {
+                    Token::Uint(value) => Some(value),
+                    _ => None,
+                })
+                .collect();
+            if bits <= 64 {
+                let values: Vec<u64> = values.iter().map(|value| value.as_u64()).collect();
+                return vec![Series::new(name, values)];
+            }
+            let values: Vec<Option<U256>> = values.into_iter().map(Some).collect();
+            create_u256_columns(name, &values, u256)
+        }
+        ParamType::Int(bits) => {
+            let values: Vec<I256> = values
+                .into_iter()
+                .filter_map(|value| match value {
+                    Token::Int(value) => Some(value),
+                    _ => None,
+                })
+                .collect();
+            if bits <= 64 {
+                let values: Vec<i64> = values.iter().map(|value| value.as_i64()).collect();
+                return vec![Series::new(name, values)];
+            }
+            //polars has no wider integers, the decimal digits keep them exact
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            vec![Series::new(name, values)]
+        }
+        ParamType::String => {
+            let values: Vec<String> = values
+                .into_iter()
+                .filter_map(|value| match value {
+                    Token::String(value) => Some(value),
+                    _ => None,
+                })
+                .collect();
+            vec![Series::new(name, values)]
+        }
+        ParamType::Address | ParamType::FixedBytes(_) | ParamType::Bytes => {
+            vec![create_bytes_column(name, values, binary)]
+        }
+    }
+}
+
+//0x-prefixed hex, or the raw bytes with `--binary`
+fn create_bytes_column(name: &str, values: Vec<Token>, binary: bool) -> Series {
+    let values = values.into_iter().filter_map(|value| match value {
+        Token::Bytes(value) => Some(value),
+        _ => None,
+    });
+    if binary {
+        return Series::new(name, values.collect::<Vec<Vec<u8>>>());
+    }
+    let values: Vec<String> = values
+        .map(|value| format!("0x{}", hex::encode(value)))
+        .collect();
+    Series::new(name, values)
+}
+
+fn get_param_name(name: Option<String>, index: usize) -> String {
+    match name {
+        Some(name) if !name.is_empty() => name,
+        _ => format!("param{}", index),
+    }
+}
+
+fn get_word(bytes: &[u8], offset: usize) -> Result<&[u8; 32]> {
+    bytes
+        .get(offset..offset + 32)
+        .and_then(|word| word.try_into().ok())
+        .ok_or_else(|| anyhow!("Expected 32 bytes at offset {}", offset))
+}
+
+fn get_offset(word: &[u8; 32]) -> Result<usize> {
+    let offset = U256::from_be_bytes(*word);
+    usize::try_from(offset).map_err(|_| anyhow!("Invalid offset {}", offset))
+}
+
+fn decode_word(kind: ParamType, word: &[u8; 32]) -> Result<Token> {
+    Ok(match kind {
+        ParamType::Address => Token::Bytes(word[12..].to_vec()),
+        ParamType::Bool => Token::Bool(word[31] != 0),
+        ParamType::Uint(_) => Token::Uint(U256::from_be_bytes(*word)),
+        ParamType::Int(_) => Token::Int(I256::from_be_bytes(*word)),
+        ParamType::FixedBytes(size) => Token::Bytes(word[..size].to_vec()),
+        ParamType::Bytes | ParamType::String => {
+            return Err(anyhow!("{} is not a static type", kind.get_name()))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Event;
+    use crate::cli::config::U256Format;
+    use crate::models::{Block, BlockHeader, Log, Scalar};
+    use anyhow::Result;
+    use polars::prelude::AnyValue;
+
+    #[test]
+    fn test_decode_transfer() -> Result<()> {
+        let event =
+            Event::parse("Transfer(address indexed from, address indexed to, uint256 value)")?;
+        assert_eq!(
+            event.topic0,
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+        let word = |value: &str| format!("{:0>64}", value);
+        let log = Log {
+            log_index: Some(Scalar::Number(3)),
+            transaction_index: Some(Scalar::Number(1)),
+            address: Some(Scalar::String(format!("0x{}", "a0".repeat(20)))),
+            topics: Some(vec![
+                event.topic0.clone(),
+                format!("0x{}", word(&"11".repeat(20))),
+                format!("0x{}", word(&"22".repeat(20))),
+            ]),
+            data: Some(Scalar::String(format!("0x{}", word("3635c9adc5dea00000")))),
+            ..Default::default()
+        };
+        //an ERC-721 transfer has the token id as a fourth topic
+        let mut nft = log.clone();
+        nft.topics
+            .as_mut()
+            .unwrap()
+            .push(format!("0x{}", word("1")));
+        let block = Block {
+            header: BlockHeader {
+                number: 17000000,
+                ..Default::default()
+            },
+            transactions: vec![],
+            logs: vec![log, nft],
+            traces: vec![],
+            state_diffs: vec![],
+        };
+
+        let df = super::decode_events(&event, &[&block], U256Format::String, false)?;
+        assert_eq!(df.height(), 1);
+        assert_eq!(
+            df.column("from")?.get(0)?,
+            AnyValue::String(&format!("0x{}", "11".repeat(20)))
+        );
+        assert_eq!(
+            df.column("value")?.get(0)?,
+            AnyValue::String("1000000000000000000000")
+        );
+        assert_eq!(df.column("logIndex")?.get(0)?, AnyValue::UInt64(3));
+        Ok(())
+    }
+}
@@ -1,19 +1,24 @@
 //use polars::prelude::*;
-use crate::cli::config::Dataset;
+use crate::cli::config::{Config, Dataset, Partition, Range, U256Format};
 
-use polars::prelude::{DataFrame, ParquetCompression, ParquetWriter, Series};
-use serde_json::Value;
-use std::collections::HashMap;
+use polars::prelude::{DataFrame, Series};
+use std::collections::{BTreeMap, HashMap};
 
+use crate::export::abi::decode_events;
 use crate::export::fields::{create_columns_from_field_data, create_field_data, FieldData};
-use anyhow::Error;
+use crate::export::formats::write_dataframe;
+use crate::export::naming::{get_file_name, get_partition_dir};
+use crate::models::Block;
+use anyhow::{Context, Error};
 use std::fs::{self, File};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 fn convert_to_dataframe(
     dataset: Dataset,
-    json_data: Vec<Value>,
+    blocks: &[&Block],
     fields: Vec<&str>,
+    u256: U256Format,
+    binary: bool,
 ) -> Result<DataFrame, Error> {
     let data_fields: Vec<(&str, FieldData)> = fields
         .iter()
@@ -30,116 +35,290 @@ fn convert_to_dataframe(
         .map(|(name, data)| (name.to_string(), data))
         .collect();
     //put loop inside func, return mutable reference to fieldmap
-    field_map = process_json_object(json_data, field_map, &fields, &dataset).unwrap(); //todo change to anyhow
-                                                                                       //create series from fields
-    let columns: Vec<Series> = create_columns_from_field_data(&field_map, &fields);
+    field_map = process_blocks(blocks, field_map, &fields, &dataset)?;
+    //create series from fields
+    let columns: Vec<Series> = create_columns_from_field_data(&field_map, &fields, u256, binary)?;
 
     let df = DataFrame::new(columns)
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
     Ok(df)
 }
-//pass fields here
+//`bounds` names the file after a fixed block range instead of the blocks it holds
 pub fn save_to_file(
-    dataset: Dataset,
-    fields_vec: &Vec<String>,
-    json_data: Vec<Value>,
+    config: &Config,
+    blocks: Vec<Block>,
+    bounds: Option<Range>,
+) -> Result<(), Error> {
+    let blocks: Vec<&Block> = blocks.iter().collect();
+    for table in get_tables(config) {
+        for (folder, blocks, bounds) in get_partitions(&table, &blocks, bounds.clone())? {
+            write_file(&table, folder, &blocks, bounds)?;
+        }
+    }
+    Ok(())
+}
+
+//removes the files `save_to_file` wrote for the same blocks and bounds, e.g. a file cut short
+pub fn remove_files(config: &Config, blocks: &[&Block], bounds: Range) -> Result<(), Error> {
+    for table in get_tables(config) {
+        for (folder, blocks, bounds) in get_partitions(&table, blocks, Some(bounds.clone()))? {
+            match fs::remove_file(get_file_path(&table, &folder, &blocks, bounds)?) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+//every dataset is written from the same blocks, so their files cover the same ranges
+fn get_tables(config: &Config) -> Vec<Config> {
+    let mut tables = Vec::new();
+    for dataset in &config.datasets {
+        tables.push(config.for_dataset(dataset));
+        //decoded events are written next to the logs they come from
+        if dataset.dataset == Dataset::Logs {
+            for event in &config.events {
+                tables.push(config.for_dataset(dataset).for_event(event));
+            }
+        }
+    }
+    tables
+}
+
+//the folder of a partition with its blocks and bounds
+type PartitionBlocks<'a> = (PathBuf, Vec<&'a Block>, Option<Range>);
+
+//every partition, only the output dir without partitions
+fn get_partitions<'a>(
+    config: &Config,
+    blocks: &[&'a Block],
+    bounds: Option<Range>,
+) -> Result<Vec<PartitionBlocks<'a>>, Error> {
+    let partition = match config.partition {
+        Some(partition) => partition,
+        None => return Ok(vec![(config.output_dir.clone(), blocks.to_vec(), bounds)]),
+    };
+    //blocks arrive in order, so each partition gets a contiguous run of blocks
+    let mut partitions: BTreeMap<String, Vec<&Block>> = BTreeMap::new();
+    for &block in blocks {
+        let partition_dir = get_partition_dir(config, partition, block)?;
+        partitions.entry(partition_dir).or_default().push(block);
+    }
+    Ok(partitions
+        .into_iter()
+        .map(|(partition_dir, blocks)| {
+            let bounds = match (partition, &bounds) {
+                (Partition::BlockBucket(size), Some(bounds)) => {
+                    let bucket = blocks[0].header.number / size * size;
+                    Some(Range {
+                        start: bounds.start.max(bucket),
+                        end: bounds.end.min(bucket + size),
+                    })
+                }
+                _ => None,
+            };
+            (config.output_dir.join(partition_dir), blocks, bounds)
+        })
+        .collect())
+}
+
+fn get_file_path(
+    config: &Config,
+    folder: &Path,
+    blocks: &[&Block],
+    bounds: Option<Range>,
+) -> Result<PathBuf, Error> {
+    let (first_block, last_block) = match bounds {
+        Some(bounds) => (bounds.start, bounds.end - 1),
+        None => {
+            let first_block = blocks
+                .first()
+                .map(|b| b.header.number)
+                .ok_or_else(|| Error::msg("No blocks to write"))?;
+            let last_block = blocks
+                .last()
+                .map(|b| b.header.number)
+                .ok_or_else(|| Error::msg("No blocks to write"))?;
+            (first_block, last_block)
+        }
+    };
+    Ok(folder.join(format!(
+        "{}.{}",
+        get_file_name(config, first_block, last_block)?,
+        config.format.get_extension()
+    )))
+}
+
+//pass fields here
+fn write_file(
+    config: &Config,
+    folder: PathBuf,
+    blocks: &[&Block],
+    bounds: Option<Range>,
 ) -> Result<(), Error> {
-    let fields = fields_vec.iter().map(|s| s.as_str()).collect();
-
-    let first_block = json_data
-        .first()
-        .and_then(|b| b["header"]["number"].as_u64())
-        .ok_or_else(|| {
-            Error::msg("Invalid block data format: 'number' field missing or not a u64")
-        })?;
-    let last_block = json_data
-        .last()
-        .and_then(|b| b["header"]["number"].as_u64())
-        .ok_or_else(|| {
-            Error::msg("Invalid block data format: 'number' field missing or not a u64")
-        })?;
-
-    let mut df = convert_to_dataframe(dataset, json_data, fields)?;
-    let folder = Path::new("data");
-
-    if !folder.exists() {
+    let dataset = config.dataset;
+    let fields = config.fields.iter().map(|s| s.as_str()).collect();
+    let file_path = get_file_path(config, &folder, blocks, bounds)?;
+
+    let mut df = match &config.event {
+        Some(event) => decode_events(event, blocks, config.u256, config.binary)?,
+        None => convert_to_dataframe(dataset, blocks, fields, config.u256, config.binary)?,
+    };
+    if let Some(folder) = file_path.parent() {
         fs::create_dir_all(folder)?;
     }
-    //TODO name file with blocks num and data name
-    let file_path = format!(
-        "data/{}_{}-{}.parquet",
-        dataset.get_name(),
-        first_block,
-        last_block
-    );
 
     let file =
         File::create(file_path).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    ParquetWriter::new(file)
-        .with_compression(ParquetCompression::Snappy)
-        .finish(&mut df)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    write_dataframe(&mut df, config.format, &config.parquet, file)?;
 
     Ok(())
 }
 
-fn process_json_object(
-    json_data: Vec<Value>,
+fn process_blocks(
+    blocks: &[&Block],
     mut field_map: HashMap<String, FieldData>,
     fields: &[&str],
     dataset: &Dataset,
 ) -> Result<HashMap<String, FieldData>, Error> {
-    for json_obj in json_data {
-        match dataset {
-            Dataset::Blocks => {
-                if let Some(header) = json_obj.get("header") {
-                    //check types here TODO
-                    fields.iter().for_each(|field| {
-                        if let Some(data) = field_map.get_mut(*field) {
-                            if let Some(value) = header.get(*field) {
-                                if let Err(e) = data.add_value(value) {
-                                    eprintln!("Error processing value: {}", e);
-                                }
-                            }
-                        }
-                    });
-                }
-            }
-            Dataset::Transactions => {
-                if let Some(tx_list) = json_obj.get("transactions") {
-                    //check types here TODO
-                    fields.iter().for_each(|field| {
-                        if let Some(data) = field_map.get_mut(*field) {
-                            for tx in tx_list.as_array().unwrap() {
-                                if let Some(value) = tx.get(*field) {
-                                    if let Err(e) = data.add_value(value) {
-                                        eprintln!("Error processing value: {}", e);
-                                    }
-                                }
-                            }
-                        }
-                    });
-                }
+    for block in blocks {
+        for field in fields {
+            if let Some(data) = field_map.get_mut(*field) {
+                let result = match dataset {
+                    Dataset::Blocks => data.add_block(&block.header),
+                    Dataset::Transactions => block
+                        .transactions
+                        .iter()
+                        .try_for_each(|tx| data.add_transaction(&block.header, tx)),
+                    Dataset::Logs => block
+                        .logs
+                        .iter()
+                        .try_for_each(|log| data.add_log(&block.header, log)),
+                    Dataset::Traces => block
+                        .traces
+                        .iter()
+                        .try_for_each(|trace| data.add_trace(trace)),
+                    Dataset::StateDiffs => block
+                        .state_diffs
+                        .iter()
+                        .try_for_each(|diff| data.add_state_diff(diff)),
+                };
+                //a skipped value would leave the column shorter than the others
+                result.with_context(|| {
+                    format!("Invalid {} in block {}", field, block.header.number)
+                })?;
             }
-            Dataset::Logs => {
-                if let Some(log_list) = json_obj.get("logs") {
-                    //check types here TODO
-                    fields.iter().for_each(|field| {
-                        if let Some(data) = field_map.get_mut(*field) {
-                            for log in log_list.as_array().unwrap() {
-                                if let Some(value) = log.get(*field) {
-                                    if let Err(e) = data.add_value(value) {
-                                        eprintln!("Error processing value: {}", e);
-                                    }
-                                }
-                            }
-                        }
-                    });
-                }
-            } // _ => panic!("Dataset not found"),
         }
     }
 
     Ok(field_map)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{convert_to_dataframe, get_partitions};
+    use crate::cli::config::{Config, Dataset, Range, U256Format};
+    use crate::cli::opts::Opts;
+    use crate::models::{Block, BlockHeader, Scalar, Transaction};
+    use anyhow::Result;
+
+    fn get_blocks(range: std::ops::Range<u64>) -> Vec<Block> {
+        range
+            .map(|number| Block {
+                header: BlockHeader {
+                    number,
+                    ..Default::default()
+                },
+                transactions: vec![],
+                logs: vec![],
+                traces: vec![],
+                state_diffs: vec![],
+            })
+            .collect()
+    }
+
+    //a `--blocks-per-file` file spanning two buckets is written as one file per bucket
+    #[test]
+    fn test_bounds_cut_at_buckets() -> Result<()> {
+        let config: Config = Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("1500:3000".to_owned()),
+            partition: Some("block".to_owned()),
+            partition_size: Some(1000),
+            blocks_per_file: Some(1500),
+            ..Default::default()
+        }
+        .try_into()?;
+        let blocks = get_blocks(1500..3000);
+        let blocks: Vec<&Block> = blocks.iter().collect();
+        let bounds = Range {
+            start: 1500,
+            end: 3000,
+        };
+        let partitions = get_partitions(&config, &blocks, Some(bounds))?;
+        let folder = config.output_dir.join("ethereum-mainnet/dataset=logs");
+        assert_eq!(
+            partitions
+                .iter()
+                .map(|(folder, blocks, bounds)| (folder.clone(), blocks.len(), bounds.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    folder.join("block_bucket=1000"),
+                    500,
+                    Some(Range {
+                        start: 1500,
+                        end: 2000
+                    })
+                ),
+                (
+                    folder.join("block_bucket=2000"),
+                    1000,
+                    Some(Range {
+                        start: 2000,
+                        end: 3000
+                    })
+                ),
+            ]
+        );
+        //without fixed bounds the files are named after their blocks
+        let partitions = get_partitions(&config, &blocks, None)?;
+        assert!(partitions.iter().all(|(_, _, bounds)| bounds.is_none()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_value_names_block_and_field() {
+        let transaction = |v: &str| Transaction {
+            v: Some(Scalar::String(v.to_owned())),
+            value: Some(Scalar::String("0x1".to_owned())),
+            ..Default::default()
+        };
+        let block = Block {
+            header: BlockHeader {
+                number: 17000000,
+                ..Default::default()
+            },
+            transactions: vec![transaction("0x1b"), transaction("0xzz")],
+            logs: vec![],
+            traces: vec![],
+            state_diffs: vec![],
+        };
+        //the second row used to be skipped, failing later on columns of different lengths
+        let error = convert_to_dataframe(
+            Dataset::Transactions,
+            &[&block],
+            vec!["value", "v"],
+            U256Format::String,
+            false,
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", error).starts_with("Invalid v in block 17000000"),
+            "{:#}",
+            error
+        );
+    }
+}
@@ -1,16 +1,21 @@
 use std::collections::HashMap;
 
 use crate::cli::config::Dataset;
+use crate::cli::config::U256Format;
+use crate::export::u256::{create_u256_columns, parse_u256};
+use crate::models::{BlockHeader, Log, Scalar, StateDiff, Trace, Transaction};
 use anyhow::Error;
 use anyhow::Result;
+use ethnum::U256;
 use polars::prelude::{NamedFrom, Series};
-use serde_json::Value;
-use utils::utils::hex_str_to_u64;
+use utils::utils::{hex_str_to_u64, hex_to_bytes};
 #[derive(Debug)]
 pub enum FieldData {
     BlocksData(BlockFieldData),
     TransactionsData(TransactionsFieldData),
     LogsData(LogFieldData),
+    TracesData(TraceFieldData),
+    StateDiffsData(StateDiffFieldData),
 }
 #[derive(Debug)]
 pub enum LogFieldData {
@@ -22,6 +27,49 @@ pub enum LogFieldData {
     Address(Vec<String>),
     Data(Vec<String>),
     Topics(Vec<Vec<String>>),
+    BlockNumber(Vec<u64>),
+    BlockHash(Vec<String>),
+    Timestamp(Vec<u64>),
+}
+#[derive(Debug)]
+pub enum TraceFieldData {
+    TransactionIndex(Vec<Option<u64>>),
+    TraceAddress(Vec<Vec<u64>>),
+    Subtraces(Vec<Option<u64>>),
+    Type(Vec<Option<String>>),
+    Error(Vec<Option<String>>),
+    RevertReason(Vec<Option<String>>),
+    CreateFrom(Vec<Option<String>>),
+    CreateValue(Vec<Option<U256>>),
+    CreateGas(Vec<Option<u64>>),
+    CreateInit(Vec<Option<String>>),
+    CreateResultGasUsed(Vec<Option<u64>>),
+    CreateResultCode(Vec<Option<String>>),
+    CreateResultAddress(Vec<Option<String>>),
+    CallFrom(Vec<Option<String>>),
+    CallTo(Vec<Option<String>>),
+    CallValue(Vec<Option<U256>>),
+    CallGas(Vec<Option<u64>>),
+    CallInput(Vec<Option<String>>),
+    CallSighash(Vec<Option<String>>),
+    CallType(Vec<Option<String>>),
+    CallResultGasUsed(Vec<Option<u64>>),
+    CallResultOutput(Vec<Option<String>>),
+    SuicideAddress(Vec<Option<String>>),
+    SuicideRefundAddress(Vec<Option<String>>),
+    SuicideBalance(Vec<Option<U256>>),
+    RewardAuthor(Vec<Option<String>>),
+    RewardValue(Vec<Option<U256>>),
+    RewardType(Vec<Option<String>>),
+}
+#[derive(Debug)]
+pub enum StateDiffFieldData {
+    TransactionIndex(Vec<Option<u64>>),
+    Address(Vec<Option<String>>),
+    Key(Vec<Option<String>>),
+    Kind(Vec<Option<String>>),
+    Prev(Vec<Option<String>>),
+    Next(Vec<Option<String>>),
 }
 #[derive(Debug)]
 pub enum BlockFieldData {
@@ -35,9 +83,9 @@ pub enum BlockFieldData {
     ReceiptsRoot(Vec<String>),
     GasUsed(Vec<u64>),
     ExtraData(Vec<String>),
-    BaseFeePerGas(Vec<u64>),
+    BaseFeePerGas(Vec<Option<U256>>),
     LogsBloom(Vec<String>),
-    TotalDifficulty(Vec<u64>),
+    TotalDifficulty(Vec<Option<U256>>),
     Size(Vec<u64>),
 }
 #[derive(Debug)]
@@ -45,136 +93,206 @@ pub enum TransactionsFieldData {
     Id(Vec<String>),
     TransactionIndex(Vec<u64>),
     From(Vec<String>),
-    To(Vec<String>),
+    //null for contract creations
+    To(Vec<Option<String>>),
     Hash(Vec<String>),
     Gas(Vec<u64>),
-    GasPrice(Vec<u64>),
-    MaxFeePerGas(Vec<u64>),
-    MaxPriorityFeePerGas(Vec<u64>),
+    GasPrice(Vec<Option<U256>>),
+    MaxFeePerGas(Vec<Option<U256>>),
+    MaxPriorityFeePerGas(Vec<Option<U256>>),
     Input(Vec<String>),
     Nonce(Vec<u64>),
-    Value(Vec<String>),
-    V(Vec<u64>),
+    Value(Vec<Option<U256>>),
+    V(Vec<Option<u64>>),
     R(Vec<String>),
     S(Vec<String>),
     YParity(Vec<u64>),
     ChainId(Vec<u64>),
     GasUsed(Vec<u64>),
     CumulativeGasUsed(Vec<u64>),
-    EffectiveGasPrice(Vec<u64>),
+    EffectiveGasPrice(Vec<Option<U256>>),
     ContractAddress(Vec<String>),
     Type(Vec<u64>),
     Status(Vec<u64>),
     Sighash(Vec<String>),
+    BlockNumber(Vec<u64>),
+    BlockHash(Vec<String>),
+    Timestamp(Vec<u64>),
 }
 
 impl FieldData {
-    pub fn add_value(&mut self, value: &Value) -> Result<()> {
+    //the header fills the block context columns
+    pub fn add_log(&mut self, header: &BlockHeader, log: &Log) -> Result<()> {
         match self {
-            FieldData::BlocksData(_data) => self.add_blocks_value(value),
-            FieldData::TransactionsData(_data) => self.add_transactions_value(value),
-            FieldData::LogsData(_data) => self.add_logs_value(value),
-            //logs
-            //traces
-            //_ => panic!("Unsupported type"),
+            Self::LogsData(data) => {
+                match data {
+                    LogFieldData::Id(vec) => vec.push(get_string(&log.id)?),
+                    LogFieldData::TransactionHash(vec) => {
+                        vec.push(get_string(&log.transaction_hash)?)
+                    }
+                    LogFieldData::LogIndex(vec) => vec.push(get_u64(&log.log_index)?),
+                    LogFieldData::TransactionIndex(vec) => {
+                        vec.push(get_u64(&log.transaction_index)?)
+                    }
+                    LogFieldData::Address(vec) => vec.push(get_string(&log.address)?),
+                    LogFieldData::Data(vec) => vec.push(get_string(&log.data)?),
+                    LogFieldData::Topics(vec) => {
+                        let topics = log
+                            .topics
+                            .clone()
+                            .ok_or_else(|| Error::msg("Expected an array"))?;
+                        vec.push(topics);
+                    }
+                    LogFieldData::BlockNumber(vec) => vec.push(header.number),
+                    LogFieldData::BlockHash(vec) => vec.push(get_string(&header.hash)?),
+                    LogFieldData::Timestamp(vec) => vec.push(get_timestamp(header)?),
+                }
+                Ok(())
+            }
+            _ => Err(Error::msg("Unsupported logs type")),
         }
     }
 
-    pub fn add_logs_value(&mut self, value: &Value) -> Result<()> {
+    //fields that don't apply to a trace type are omitted, they are pushed as nulls
+    pub fn add_trace(&mut self, trace: &Trace) -> Result<()> {
         match self {
-            Self::LogsData(data) => {
+            Self::TracesData(data) => {
                 match data {
-                    LogFieldData::Id(vec) | LogFieldData::TransactionHash(vec) => {
-                        let string_value = value
-                            .as_str()
-                            .ok_or_else(|| Error::msg("Expected a string"))?;
-                        vec.push(string_value.to_string());
+                    TraceFieldData::Type(vec) => vec.push(get_optional_string(&trace.trace_type)),
+                    TraceFieldData::Error(vec) => vec.push(get_optional_string(&trace.error)),
+                    TraceFieldData::RevertReason(vec) => {
+                        vec.push(get_optional_string(&trace.revert_reason))
                     }
-                    LogFieldData::LogIndex(vec) | LogFieldData::TransactionIndex(vec) => {
-                        let number_value = value
-                            .as_u64()
-                            .ok_or_else(|| Error::msg("Expected a u64 number"))?;
-                        vec.push(number_value);
+                    TraceFieldData::CreateFrom(vec) => {
+                        vec.push(get_optional_string(&trace.create_from))
                     }
-
-                    // Add other cases as necessary
-                    LogFieldData::Address(vec) | LogFieldData::Data(vec) => {
-                        let string_value = value
-                            .as_str()
-                            .ok_or_else(|| Error::msg("Expected a string"))?;
-                        vec.push(string_value.to_string());
+                    TraceFieldData::CreateValue(vec) => {
+                        vec.push(get_optional_u256(&trace.create_value)?)
                     }
-
-                    LogFieldData::Topics(vec) => {
-                        let topics_array = value
-                            .as_array()
+                    TraceFieldData::CreateInit(vec) => {
+                        vec.push(get_optional_string(&trace.create_init))
+                    }
+                    TraceFieldData::CreateResultCode(vec) => {
+                        vec.push(get_optional_string(&trace.create_result_code))
+                    }
+                    TraceFieldData::CreateResultAddress(vec) => {
+                        vec.push(get_optional_string(&trace.create_result_address))
+                    }
+                    TraceFieldData::CallFrom(vec) => {
+                        vec.push(get_optional_string(&trace.call_from))
+                    }
+                    TraceFieldData::CallTo(vec) => vec.push(get_optional_string(&trace.call_to)),
+                    TraceFieldData::CallValue(vec) => {
+                        vec.push(get_optional_u256(&trace.call_value)?)
+                    }
+                    TraceFieldData::CallInput(vec) => {
+                        vec.push(get_optional_string(&trace.call_input))
+                    }
+                    TraceFieldData::CallSighash(vec) => {
+                        vec.push(get_optional_string(&trace.call_sighash))
+                    }
+                    TraceFieldData::CallType(vec) => {
+                        vec.push(get_optional_string(&trace.call_type))
+                    }
+                    TraceFieldData::CallResultOutput(vec) => {
+                        vec.push(get_optional_string(&trace.call_result_output))
+                    }
+                    TraceFieldData::SuicideAddress(vec) => {
+                        vec.push(get_optional_string(&trace.suicide_address))
+                    }
+                    TraceFieldData::SuicideRefundAddress(vec) => {
+                        vec.push(get_optional_string(&trace.suicide_refund_address))
+                    }
+                    TraceFieldData::SuicideBalance(vec) => {
+                        vec.push(get_optional_u256(&trace.suicide_balance)?)
+                    }
+                    TraceFieldData::RewardAuthor(vec) => {
+                        vec.push(get_optional_string(&trace.reward_author))
+                    }
+                    TraceFieldData::RewardValue(vec) => {
+                        vec.push(get_optional_u256(&trace.reward_value)?)
+                    }
+                    TraceFieldData::RewardType(vec) => {
+                        vec.push(get_optional_string(&trace.reward_type))
+                    }
+                    TraceFieldData::TransactionIndex(vec) => {
+                        vec.push(get_optional_u64(&trace.transaction_index))
+                    }
+                    TraceFieldData::Subtraces(vec) => vec.push(get_optional_u64(&trace.subtraces)),
+                    TraceFieldData::CreateGas(vec) => {
+                        vec.push(get_optional_hex_u64(&trace.create_gas)?)
+                    }
+                    TraceFieldData::CreateResultGasUsed(vec) => {
+                        vec.push(get_optional_hex_u64(&trace.create_result_gas_used)?)
+                    }
+                    TraceFieldData::CallGas(vec) => {
+                        vec.push(get_optional_hex_u64(&trace.call_gas)?)
+                    }
+                    TraceFieldData::CallResultGasUsed(vec) => {
+                        vec.push(get_optional_hex_u64(&trace.call_result_gas_used)?)
+                    }
+                    TraceFieldData::TraceAddress(vec) => {
+                        let trace_address = trace
+                            .trace_address
+                            .clone()
                             .ok_or_else(|| Error::msg("Expected an array"))?;
-                        let mut topics = Vec::new();
-                        for topic in topics_array {
-                            let topic_str = topic
-                                .as_str()
-                                .ok_or_else(|| Error::msg("Expected a string in array"))?;
-                            topics.push(topic_str.to_string());
-                        }
-                        vec.push(topics);
+                        vec.push(trace_address);
                     }
                 }
                 Ok(())
             }
-            _ => Err(Error::msg("Unsupported logs type")),
+            _ => Err(Error::msg("Unsupported traces type")),
+        }
+    }
+
+    //prev is omitted for created slots and next for deleted ones
+    pub fn add_state_diff(&mut self, diff: &StateDiff) -> Result<()> {
+        match self {
+            Self::StateDiffsData(data) => {
+                match data {
+                    StateDiffFieldData::TransactionIndex(vec) => {
+                        vec.push(get_optional_u64(&diff.transaction_index))
+                    }
+                    StateDiffFieldData::Address(vec) => {
+                        vec.push(get_optional_string(&diff.address))
+                    }
+                    StateDiffFieldData::Key(vec) => vec.push(get_optional_string(&diff.key)),
+                    StateDiffFieldData::Kind(vec) => vec.push(get_optional_string(&diff.kind)),
+                    StateDiffFieldData::Prev(vec) => vec.push(get_optional_string(&diff.prev)),
+                    StateDiffFieldData::Next(vec) => vec.push(get_optional_string(&diff.next)),
+                }
+                Ok(())
+            }
+            _ => Err(Error::msg("Unsupported state diffs type")),
         }
     }
 
-    pub fn add_blocks_value(&mut self, value: &Value) -> Result<()> {
+    pub fn add_block(&mut self, header: &BlockHeader) -> Result<()> {
         match self {
             Self::BlocksData(data) => {
                 match data {
-                    BlockFieldData::Hash(vec)
-                    | BlockFieldData::ParentHash(vec)
-                    | BlockFieldData::Miner(vec)
-                    | BlockFieldData::StateRoot(vec)
-                    | BlockFieldData::TransactionsRoot(vec)
-                    | BlockFieldData::ReceiptsRoot(vec)
-                    | BlockFieldData::ExtraData(vec)
-                    | BlockFieldData::LogsBloom(vec) => {
-                        let string_value = value
-                            .as_str()
-                            .ok_or_else(|| Error::msg("Expected a string"))?;
-                        vec.push(string_value.to_string());
-                    }
-                    BlockFieldData::Number(vec) => {
-                        let number_value = value
-                            .as_u64()
-                            .ok_or_else(|| Error::msg("Expected a u64 number"))?;
-                        vec.push(number_value);
-                    }
-                    //BlockFieldData::Number(vec)
-                    //| BlockFieldData::GasUsed(vec)
-                    BlockFieldData::TotalDifficulty(vec) | BlockFieldData::Size(vec) => {
-                        let number_value = value
-                            .as_u64()
-                            .ok_or_else(|| Error::msg("Expected a u64 number"))?;
-                        vec.push(number_value);
-                    }
-                    BlockFieldData::GasUsed(vec) => {
-                        let str_value = value
-                            .as_str()
-                            .ok_or_else(|| Error::msg("Expected a u64 number"))?;
-                        let number_value = hex_str_to_u64(str_value)?;
-                        vec.push(number_value);
-                    }
-
-                    BlockFieldData::Timestamp(vec) => {
-                        let timestamp_value = value
-                            .as_f64()
-                            .ok_or_else(|| Error::msg("Expected a f64 number"))?
-                            as u64;
-                        vec.push(timestamp_value);
+                    BlockFieldData::Hash(vec) => vec.push(get_string(&header.hash)?),
+                    BlockFieldData::ParentHash(vec) => vec.push(get_string(&header.parent_hash)?),
+                    BlockFieldData::Miner(vec) => vec.push(get_string(&header.miner)?),
+                    BlockFieldData::StateRoot(vec) => vec.push(get_string(&header.state_root)?),
+                    BlockFieldData::TransactionsRoot(vec) => {
+                        vec.push(get_string(&header.transactions_root)?)
+                    }
+                    BlockFieldData::ReceiptsRoot(vec) => {
+                        vec.push(get_string(&header.receipts_root)?)
+                    }
+                    BlockFieldData::ExtraData(vec) => vec.push(get_string(&header.extra_data)?),
+                    BlockFieldData::LogsBloom(vec) => vec.push(get_string(&header.logs_bloom)?),
+                    BlockFieldData::Number(vec) => vec.push(header.number),
+                    BlockFieldData::TotalDifficulty(vec) => {
+                        vec.push(get_optional_u256(&header.total_difficulty)?)
                     }
+                    BlockFieldData::Size(vec) => vec.push(get_u64(&header.size)?),
+                    BlockFieldData::GasUsed(vec) => vec.push(get_hex_u64(&header.gas_used)?),
+                    BlockFieldData::Timestamp(vec) => vec.push(get_timestamp(header)?),
+                    //null before london
                     BlockFieldData::BaseFeePerGas(vec) => {
-                        // Use a default value of 0 if the conversion fails
-                        let base_fee = value.as_u64().unwrap_or(0);
-                        vec.push(base_fee);
+                        vec.push(get_optional_u256(&header.base_fee_per_gas)?)
                     }
                 }
                 Ok(())
@@ -183,59 +301,52 @@ impl FieldData {
         }
     }
 
-    pub fn add_transactions_value(&mut self, value: &Value) -> Result<()> {
+    pub fn add_transaction(&mut self, header: &BlockHeader, tx: &Transaction) -> Result<()> {
         match self {
             Self::TransactionsData(data) => {
                 match data {
-                    TransactionsFieldData::Id(vec)
-                    | TransactionsFieldData::From(vec)
-                    | TransactionsFieldData::Hash(vec)
-                    | TransactionsFieldData::Input(vec)
-                    | TransactionsFieldData::R(vec)
-                    | TransactionsFieldData::S(vec)
-                    | TransactionsFieldData::ContractAddress(vec)
-                    | TransactionsFieldData::Sighash(vec) => {
-                        let string_value = value
-                            .as_str()
-                            .ok_or_else(|| Error::msg("Expected a string"))?;
-                        vec.push(string_value.to_string());
-                    }
-                    TransactionsFieldData::TransactionIndex(vec)
-                    | TransactionsFieldData::Gas(vec)
-                    | TransactionsFieldData::GasPrice(vec)
-                    | TransactionsFieldData::Nonce(vec)
-                    | TransactionsFieldData::YParity(vec)
-                    | TransactionsFieldData::ChainId(vec)
-                    //| TransactionsFieldData::GasUsed(vec)
-                    | TransactionsFieldData::CumulativeGasUsed(vec)
-                    | TransactionsFieldData::EffectiveGasPrice(vec)
-                    | TransactionsFieldData::Type(vec)
-                    | TransactionsFieldData::Status(vec) => {
-                        let number_value = value
-                            .as_u64()
-                            .ok_or_else(|| Error::msg("Expected a u64 number"))?;
-                        vec.push(number_value);
-                    },
-                    TransactionsFieldData::GasUsed(vec) => {
-                        let str_value = value
-                            .as_str()
-                            .ok_or_else(|| Error::msg("Expected a u64 number"))?;
-                        let number_value = hex_str_to_u64(str_value)?;
-                        vec.push(number_value);
-                    },
-
-                    TransactionsFieldData::To(vec) | TransactionsFieldData::Value(vec) => {
-                        // Use a default empty string if conversion fails
-                        let string_value = value.as_str().unwrap_or("").to_string();
-                        vec.push(string_value);
-                    }
-                    TransactionsFieldData::MaxFeePerGas(vec)
-                    | TransactionsFieldData::MaxPriorityFeePerGas(vec)
-                    | TransactionsFieldData::V(vec) => {
-                        // Use a default value of 0 if the conversion fails
-                        let number_value = value.as_u64().unwrap_or(0);
-                        vec.push(number_value);
+                    TransactionsFieldData::Id(vec) => vec.push(get_string(&tx.id)?),
+                    TransactionsFieldData::From(vec) => vec.push(get_string(&tx.from)?),
+                    TransactionsFieldData::Hash(vec) => vec.push(get_string(&tx.hash)?),
+                    TransactionsFieldData::Input(vec) => vec.push(get_string(&tx.input)?),
+                    TransactionsFieldData::R(vec) => vec.push(get_string(&tx.r)?),
+                    TransactionsFieldData::S(vec) => vec.push(get_string(&tx.s)?),
+                    TransactionsFieldData::ContractAddress(vec) => {
+                        vec.push(get_string(&tx.contract_address)?)
+                    }
+                    TransactionsFieldData::Sighash(vec) => vec.push(get_string(&tx.sighash)?),
+                    TransactionsFieldData::TransactionIndex(vec) => {
+                        vec.push(get_u64(&tx.transaction_index)?)
+                    }
+                    TransactionsFieldData::Gas(vec) => vec.push(get_u64(&tx.gas)?),
+                    TransactionsFieldData::GasPrice(vec) => {
+                        vec.push(get_optional_u256(&tx.gas_price)?)
+                    }
+                    TransactionsFieldData::Nonce(vec) => vec.push(get_u64(&tx.nonce)?),
+                    TransactionsFieldData::YParity(vec) => vec.push(get_u64(&tx.y_parity)?),
+                    TransactionsFieldData::ChainId(vec) => vec.push(get_u64(&tx.chain_id)?),
+                    TransactionsFieldData::CumulativeGasUsed(vec) => {
+                        vec.push(get_u64(&tx.cumulative_gas_used)?)
                     }
+                    TransactionsFieldData::EffectiveGasPrice(vec) => {
+                        vec.push(get_optional_u256(&tx.effective_gas_price)?)
+                    }
+                    TransactionsFieldData::Type(vec) => vec.push(get_u64(&tx.tx_type)?),
+                    TransactionsFieldData::Status(vec) => vec.push(get_u64(&tx.status)?),
+                    TransactionsFieldData::GasUsed(vec) => vec.push(get_hex_u64(&tx.gas_used)?),
+                    TransactionsFieldData::To(vec) => vec.push(get_optional_string(&tx.to)),
+                    TransactionsFieldData::Value(vec) => vec.push(get_optional_u256(&tx.value)?),
+                    //null for transactions before eip-1559
+                    TransactionsFieldData::MaxFeePerGas(vec) => {
+                        vec.push(get_optional_u256(&tx.max_fee_per_gas)?)
+                    }
+                    TransactionsFieldData::MaxPriorityFeePerGas(vec) => {
+                        vec.push(get_optional_u256(&tx.max_priority_fee_per_gas)?)
+                    }
+                    TransactionsFieldData::V(vec) => vec.push(get_optional_quantity(&tx.v)?),
+                    TransactionsFieldData::BlockNumber(vec) => vec.push(header.number),
+                    TransactionsFieldData::BlockHash(vec) => vec.push(get_string(&header.hash)?),
+                    TransactionsFieldData::Timestamp(vec) => vec.push(get_timestamp(header)?),
                 }
                 Ok(())
             }
@@ -244,11 +355,66 @@ impl FieldData {
     }
 }
 
+fn get_timestamp(header: &BlockHeader) -> Result<u64> {
+    let timestamp_value = header
+        .timestamp
+        .as_ref()
+        .and_then(Scalar::as_f64)
+        .ok_or_else(|| Error::msg("Expected a f64 number"))?;
+    Ok(timestamp_value as u64)
+}
+
+fn get_string(value: &Option<Scalar>) -> Result<String> {
+    get_optional_string(value).ok_or_else(|| Error::msg("Expected a string"))
+}
+
+fn get_u64(value: &Option<Scalar>) -> Result<u64> {
+    get_optional_u64(value).ok_or_else(|| Error::msg("Expected a u64 number"))
+}
+
+//quantities like gasUsed come as hex strings
+fn get_hex_u64(value: &Option<Scalar>) -> Result<u64> {
+    get_optional_hex_u64(value)?.ok_or_else(|| Error::msg("Expected a u64 number"))
+}
+
+fn get_optional_string(value: &Option<Scalar>) -> Option<String> {
+    value.as_ref().and_then(Scalar::as_str).map(String::from)
+}
+
+fn get_optional_u64(value: &Option<Scalar>) -> Option<u64> {
+    value.as_ref().and_then(Scalar::as_u64)
+}
+
+//wei values and total difficulty overflow u64
+fn get_optional_u256(value: &Option<Scalar>) -> Result<Option<U256>> {
+    value.as_ref().map(parse_u256).transpose()
+}
+
+//a number or a hex string, null when missing
+fn get_optional_quantity(value: &Option<Scalar>) -> Result<Option<u64>> {
+    match value {
+        Some(Scalar::Number(value)) => Ok(Some(*value)),
+        Some(Scalar::Float(value)) => {
+            Err(Error::msg(format!("Expected an integer, got {}", value)))
+        }
+        _ => get_optional_hex_u64(value),
+    }
+}
+
+fn get_optional_hex_u64(value: &Option<Scalar>) -> Result<Option<u64>> {
+    match value.as_ref().and_then(Scalar::as_str) {
+        Some(str_value) => Ok(Some(hex_str_to_u64(str_value)?)),
+        None => Ok(None),
+    }
+}
+
 pub fn create_field_data(field: &str, dataset: Dataset) -> Result<FieldData> {
     match dataset {
         Dataset::Blocks => create_block_field_data(field),
         Dataset::Transactions => create_transaction_field_data(field),
         Dataset::Logs => create_log_field_data(field),
+        Dataset::Traces => create_trace_field_data(field),
+        Dataset::StateDiffs => create_state_diff_field_data(field),
         //_ => panic!("Dataset not found"),
     }
 }
@@ -265,6 +431,18 @@ macro_rules! create_log_field_data {
     };
 }
 
+macro_rules! create_trace_field_data {
+    ($variant:ident) => {
+        FieldData::TracesData(TraceFieldData::$variant(vec![]))
+    };
+}
+
+macro_rules! create_state_diff_field_data {
+    ($variant:ident) => {
+        FieldData::StateDiffsData(StateDiffFieldData::$variant(vec![]))
+    };
+}
+
 macro_rules! create_transaction_field_data {
     ($variant:ident) => {
         FieldData::TransactionsData(TransactionsFieldData::$variant(vec![]))
@@ -297,6 +475,9 @@ fn create_transaction_field_data(field: &str) -> Result<FieldData> {
         "type" => Ok(create_transaction_field_data!(Type)),
         "status" => Ok(create_transaction_field_data!(Status)),
         "sighash" => Ok(create_transaction_field_data!(Sighash)),
+        "blockNumber" => Ok(create_transaction_field_data!(BlockNumber)),
+        "blockHash" => Ok(create_transaction_field_data!(BlockHash)),
+        "timestamp" => Ok(create_transaction_field_data!(Timestamp)),
         _ => Err(Error::msg(format!("Field '{}' not found", field))),
     }
 }
@@ -311,6 +492,55 @@ fn create_log_field_data(field: &str) -> Result<FieldData> {
         "address" => Ok(create_log_field_data!(Address)),
         "data" => Ok(create_log_field_data!(Data)),
         "topics" => Ok(create_log_field_data!(Topics)),
+        "blockNumber" => Ok(create_log_field_data!(BlockNumber)),
+        "blockHash" => Ok(create_log_field_data!(BlockHash)),
+        "timestamp" => Ok(create_log_field_data!(Timestamp)),
+        _ => Err(Error::msg(format!("Field '{}' not found", field))),
+    }
+}
+
+fn create_trace_field_data(field: &str) -> Result<FieldData> {
+    match field {
+        "transactionIndex" => Ok(create_trace_field_data!(TransactionIndex)),
+        "traceAddress" => Ok(create_trace_field_data!(TraceAddress)),
+        "subtraces" => Ok(create_trace_field_data!(Subtraces)),
+        "type" => Ok(create_trace_field_data!(Type)),
+        "error" => Ok(create_trace_field_data!(Error)),
+        "revertReason" => Ok(create_trace_field_data!(RevertReason)),
+        "createFrom" => Ok(create_trace_field_data!(CreateFrom)),
+        "createValue" => Ok(create_trace_field_data!(CreateValue)),
+        "createGas" => Ok(create_trace_field_data!(CreateGas)),
+        "createInit" => Ok(create_trace_field_data!(CreateInit)),
+        "createResultGasUsed" => Ok(create_trace_field_data!(CreateResultGasUsed)),
+        "createResultCode" => Ok(create_trace_field_data!(CreateResultCode)),
+        "createResultAddress" => Ok(create_trace_field_data!(CreateResultAddress)),
+        "callFrom" => Ok(create_trace_field_data!(CallFrom)),
+        "callTo" => Ok(create_trace_field_data!(CallTo)),
+        "callValue" => Ok(create_trace_field_data!(CallValue)),
+        "callGas" => Ok(create_trace_field_data!(CallGas)),
+        "callInput" => Ok(create_trace_field_data!(CallInput)),
+        "callSighash" => Ok(create_trace_field_data!(CallSighash)),
+        "callType" => Ok(create_trace_field_data!(CallType)),
+        "callResultGasUsed" => Ok(create_trace_field_data!(CallResultGasUsed)),
+        "callResultOutput" => Ok(create_trace_field_data!(CallResultOutput)),
+        "suicideAddress" => Ok(create_trace_field_data!(SuicideAddress)),
+        "suicideRefundAddress" => Ok(create_trace_field_data!(SuicideRefundAddress)),
+        "suicideBalance" => Ok(create_trace_field_data!(SuicideBalance)),
+        "rewardAuthor" => Ok(create_trace_field_data!(RewardAuthor)),
+        "rewardValue" => Ok(create_trace_field_data!(RewardValue)),
+        "rewardType" => Ok(create_trace_field_data!(RewardType)),
+        _ => Err(Error::msg(format!("Field '{}' not found", field))),
+    }
+}
+
+fn create_state_diff_field_data(field: &str) -> Result<FieldData> {
+    match field {
+        "transactionIndex" => Ok(create_state_diff_field_data!(TransactionIndex)),
+        "address" => Ok(create_state_diff_field_data!(Address)),
+        "key" => Ok(create_state_diff_field_data!(Key)),
+        "kind" => Ok(create_state_diff_field_data!(Kind)),
+        "prev" => Ok(create_state_diff_field_data!(Prev)),
+        "next" => Ok(create_state_diff_field_data!(Next)),
         _ => Err(Error::msg(format!("Field '{}' not found", field))),
     }
 }
@@ -338,130 +568,443 @@ fn create_block_field_data(field: &str) -> Result<FieldData> {
 pub fn create_columns_from_field_data(
     field_map: &HashMap<String, FieldData>,
     fields: &[&str],
-    //data: FieldData,
-) -> Vec<Series> {
+    u256: U256Format,
+    binary: bool,
+) -> Result<Vec<Series>> {
     let mut columns: Vec<Series> = vec![];
     //get dataset type here
-    fields.iter().for_each(|field| match field_map.get(*field) {
-        Some(FieldData::BlocksData(data)) => {
-            match data {
-                BlockFieldData::Hash(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::Number(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::ParentHash(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::Timestamp(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::Miner(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::StateRoot(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::TransactionsRoot(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::ReceiptsRoot(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::GasUsed(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::ExtraData(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::BaseFeePerGas(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::LogsBloom(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::TotalDifficulty(vec) => columns.push(Series::new(*field, vec)),
-                BlockFieldData::Size(vec) => columns.push(Series::new(*field, vec)),
-                //_ => panic!("{} not found", field),
-            };
-        }
-
-        Some(FieldData::TransactionsData(data)) => match data {
-            TransactionsFieldData::Id(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::TransactionIndex(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::From(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::To(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Hash(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Gas(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::GasPrice(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::MaxFeePerGas(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::MaxPriorityFeePerGas(vec) => {
-                columns.push(Series::new(*field, vec))
+    for field in fields {
+        match field_map.get(*field) {
+            Some(FieldData::BlocksData(data)) => {
+                match data {
+                    BlockFieldData::Hash(vec) => {
+                        columns.push(create_hex_column(field, vec, binary)?)
+                    }
+                    BlockFieldData::Number(vec) => columns.push(Series::new(*field, vec)),
+                    BlockFieldData::ParentHash(vec) => {
+                        columns.push(create_hex_column(field, vec, binary)?)
+                    }
+                    BlockFieldData::Timestamp(vec) => columns.push(Series::new(*field, vec)),
+                    BlockFieldData::Miner(vec) => {
+                        columns.push(create_hex_column(field, vec, binary)?)
+                    }
+                    BlockFieldData::StateRoot(vec) => {
+                        columns.push(create_hex_column(field, vec, binary)?)
+                    }
+                    BlockFieldData::TransactionsRoot(vec) => {
+                        columns.push(create_hex_column(field, vec, binary)?)
+                    }
+                    BlockFieldData::ReceiptsRoot(vec) => {
+                        columns.push(create_hex_column(field, vec, binary)?)
+                    }
+                    BlockFieldData::GasUsed(vec) => columns.push(Series::new(*field, vec)),
+                    BlockFieldData::ExtraData(vec) => {
+                        columns.push(create_hex_column(field, vec, binary)?)
+                    }
+                    BlockFieldData::BaseFeePerGas(vec) => {
+                        columns.extend(create_u256_columns(field, vec, u256))
+                    }
+                    BlockFieldData::LogsBloom(vec) => {
+                        columns.push(create_hex_column(field, vec, binary)?)
+                    }
+                    BlockFieldData::TotalDifficulty(vec) => {
+                        columns.extend(create_u256_columns(field, vec, u256))
+                    }
+                    BlockFieldData::Size(vec) => columns.push(Series::new(*field, vec)),
+                    //_ => panic!("{} not found", field),
+                };
             }
-            TransactionsFieldData::Input(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Nonce(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Value(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::V(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::R(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::S(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::YParity(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::ChainId(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::GasUsed(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::CumulativeGasUsed(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::EffectiveGasPrice(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::ContractAddress(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Type(vec) => columns.push(Series::new(*&field, vec)),
-            TransactionsFieldData::Status(vec) => columns.push(Series::new(*field, vec)),
-            TransactionsFieldData::Sighash(vec) => columns.push(Series::new(*field, vec)),
-        },
-        // Some(FieldData::LogsData(data)) => match data {
-        //     LogFieldData::Id(vec) => columns.push(Series::new(*field, vec)),
-        //     LogFieldData::LogIndex(vec) => columns.push(Series::new(*field, vec)),
-        //     LogFieldData::TransactionIndex(vec) => columns.push(Series::new(*field, vec)),
-        //     LogFieldData::TransactionHash(vec) => columns.push(Series::new(*field, vec)),
-        //     //LogFieldData::BlockHash(vec) => columns.push(Series::new(*field, vec)),
-        //     LogFieldData::Address(vec) => columns.push(Series::new(*field, vec)),
-        //     LogFieldData::Data(vec) => columns.push(Series::new(*field, vec)),
-        //     LogFieldData::Topics(vec) => {
-        //         let series_list: Vec<_> = vec
-        //             .into_iter()
-        //             .map(|v| {
-        //                 // Convert Vec<String> to Series
-        //                 Series::new("inner_series", v)
-        //             })
-        //             .collect();
-        //         // Convert the list of Series into a ListChunked
-        //         let list_series = Series::new("topics", series_list);
-        //         columns.push(list_series)
-        //     } //check this later
-        // },
-        Some(FieldData::LogsData(data)) => match data {
-            LogFieldData::Id(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
+
+            Some(FieldData::TransactionsData(data)) => match data {
+                TransactionsFieldData::Id(vec) => columns.push(Series::new(*field, vec)),
+                TransactionsFieldData::TransactionIndex(vec) => {
+                    columns.push(Series::new(*field, vec))
                 }
-            }
-            LogFieldData::LogIndex(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
+                TransactionsFieldData::From(vec) => {
+                    columns.push(create_hex_column(field, vec, binary)?)
                 }
-            }
-            LogFieldData::TransactionIndex(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
+                TransactionsFieldData::To(vec) => {
+                    columns.push(create_optional_hex_column(field, vec, binary)?)
                 }
-            }
-            LogFieldData::TransactionHash(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
+                TransactionsFieldData::Hash(vec) => {
+                    columns.push(create_hex_column(field, vec, binary)?)
                 }
-            }
-            //LogFieldData::BlockHash(vec) => if !vec.is_empty() { columns.push(Series::new(*field, vec)) },
-            LogFieldData::Address(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
+                TransactionsFieldData::Gas(vec) => columns.push(Series::new(*field, vec)),
+                TransactionsFieldData::GasPrice(vec)
+                | TransactionsFieldData::MaxFeePerGas(vec)
+                | TransactionsFieldData::MaxPriorityFeePerGas(vec)
+                | TransactionsFieldData::Value(vec)
+                | TransactionsFieldData::EffectiveGasPrice(vec) => {
+                    columns.extend(create_u256_columns(field, vec, u256))
                 }
-            }
-            LogFieldData::Data(vec) => {
-                if !vec.is_empty() {
-                    columns.push(Series::new(*field, vec));
+                TransactionsFieldData::Input(vec) => {
+                    columns.push(create_hex_column(field, vec, binary)?)
                 }
-            }
-            LogFieldData::Topics(vec) => {
-                let series_list: Vec<_> = vec
-                    .into_iter()
-                    .filter(|v| !v.is_empty()) // Filter out empty vectors
-                    .map(|v| {
-                        // Convert Vec<String> to Series
-                        Series::new("inner_series", v)
-                    })
-                    .collect();
-                if !series_list.is_empty() {
-                    // Convert the list of Series into a ListChunked
-                    let list_series = Series::new("topics", series_list);
-                    columns.push(list_series);
+                TransactionsFieldData::Nonce(vec) => columns.push(Series::new(*field, vec)),
+                TransactionsFieldData::V(vec) => columns.push(Series::new(*field, vec)),
+                TransactionsFieldData::R(vec) => {
+                    columns.push(create_hex_column(field, vec, binary)?)
+                }
+                TransactionsFieldData::S(vec) => {
+                    columns.push(create_hex_column(field, vec, binary)?)
+                }
+                TransactionsFieldData::YParity(vec) => columns.push(Series::new(*field, vec)),
+                TransactionsFieldData::ChainId(vec) => columns.push(Series::new(*field, vec)),
+                TransactionsFieldData::GasUsed(vec) => columns.push(Series::new(*field, vec)),
+                TransactionsFieldData::CumulativeGasUsed(vec) => {
+                    columns.push(Series::new(*field, vec))
+                }
+                TransactionsFieldData::ContractAddress(vec) => {
+                    columns.push(create_hex_column(field, vec, binary)?)
+                }
+                TransactionsFieldData::Type(vec) => columns.push(Series::new(*&field, vec)),
+                TransactionsFieldData::Status(vec) => columns.push(Series::new(*field, vec)),
+                TransactionsFieldData::Sighash(vec) => {
+                    columns.push(create_hex_column(field, vec, binary)?)
+                }
+                TransactionsFieldData::BlockNumber(vec) | TransactionsFieldData::Timestamp(vec) => {
+                    columns.push(Series::new(field, vec))
+                }
+                TransactionsFieldData::BlockHash(vec) => {
+                    columns.push(create_hex_column(field, vec, binary)?)
+                }
+            },
+            // Some(FieldData::LogsData(data)) => match data {
+            //     LogFieldData::Id(vec) => columns.push(Series::new(*field, vec)),
+            //     LogFieldData::LogIndex(vec) => columns.push(Series::new(*field, vec)),
+            //     LogFieldData::TransactionIndex(vec) => columns.push(Series::new(*field, vec)),
+            //     LogFieldData::TransactionHash(vec) => columns.push(Series::new(*field, vec)),
+            //     //LogFieldData::BlockHash(vec) => columns.push(Series::new(*field, vec)),
+            //     LogFieldData::Address(vec) => columns.push(Series::new(*field, vec)),
+            //     LogFieldData::Data(vec) => columns.push(Series::new(*field, vec)),
+            //     LogFieldData::Topics(vec) => {
+            //         let series_list: Vec<_> = vec
+            //             .into_iter()
+            //             .map(|v| {
+            //                 // Convert Vec<String> to Series
+            //                 Series::new("inner_series", v)
+            //             })
+            //             .collect();
+            //         // Convert the list of Series into a ListChunked
+            //         let list_series = Series::new("topics", series_list);
+            //         columns.push(list_series)
+            //     } //check this later
+            // },
+            Some(FieldData::LogsData(data)) => match data {
+                LogFieldData::Id(vec) => {
+                    if !vec.is_empty() {
+                        columns.push(Series::new(*field, vec));
+                    }
+                }
+                LogFieldData::LogIndex(vec) => {
+                    if !vec.is_empty() {
+                        columns.push(Series::new(*field, vec));
+                    }
+                }
+                LogFieldData::TransactionIndex(vec) => {
+                    if !vec.is_empty() {
+                        columns.push(Series::new(*field, vec));
+                    }
+                }
+                LogFieldData::TransactionHash(vec) => {
+                    if !vec.is_empty() {
+                        columns.push(create_hex_column(field, vec, binary)?);
+                    }
+                }
+                //LogFieldData::BlockHash(vec) => if !vec.is_empty() { columns.push(Series::new(*field, vec)) },
+                LogFieldData::Address(vec) => {
+                    if !vec.is_empty() {
+                        columns.push(create_hex_column(field, vec, binary)?);
+                    }
+                }
+                LogFieldData::Data(vec) => {
+                    if !vec.is_empty() {
+                        columns.push(create_hex_column(field, vec, binary)?);
+                    }
                 }
-            } //check this later
-        },
+                LogFieldData::Topics(vec) => {
+                    let series_list: Vec<_> = vec
+                        .into_iter()
+                        .filter(|v| !v.is_empty()) // Filter out empty vectors
+                        .map(|v| {
+                            // Convert Vec<String> to Series
+                            create_hex_column("inner_series", v, binary)
+                        })
+                        .collect::<Result<_>>()?;
+                    if !series_list.is_empty() {
+                        // Convert the list of Series into a ListChunked
+                        let list_series = Series::new("topics", series_list);
+                        columns.push(list_series);
+                    }
+                } //check this later
+                LogFieldData::BlockNumber(vec) | LogFieldData::Timestamp(vec) => {
+                    if !vec.is_empty() {
+                        columns.push(Series::new(field, vec));
+                    }
+                }
+                LogFieldData::BlockHash(vec) => {
+                    if !vec.is_empty() {
+                        columns.push(create_hex_column(field, vec, binary)?);
+                    }
+                }
+            },
+            Some(FieldData::TracesData(data)) => match data {
+                TraceFieldData::TransactionIndex(vec)
+                | TraceFieldData::Subtraces(vec)
+                | TraceFieldData::CreateGas(vec)
+                | TraceFieldData::CreateResultGasUsed(vec)
+                | TraceFieldData::CallGas(vec)
+                | TraceFieldData::CallResultGasUsed(vec) => columns.push(Series::new(field, vec)),
+                TraceFieldData::CreateValue(vec)
+                | TraceFieldData::CallValue(vec)
+                | TraceFieldData::SuicideBalance(vec)
+                | TraceFieldData::RewardValue(vec) => {
+                    columns.extend(create_u256_columns(field, vec, u256))
+                }
+                TraceFieldData::TraceAddress(vec) => {
+                    let series_list: Vec<_> =
+                        vec.iter().map(|v| Series::new("inner_series", v)).collect();
+                    columns.push(Series::new(field, series_list));
+                }
+                TraceFieldData::Type(vec)
+                | TraceFieldData::Error(vec)
+                | TraceFieldData::RevertReason(vec)
+                | TraceFieldData::CallType(vec)
+                | TraceFieldData::RewardType(vec) => columns.push(Series::new(field, vec)),
+                TraceFieldData::CreateFrom(vec)
+                | TraceFieldData::CreateInit(vec)
+                | TraceFieldData::CreateResultCode(vec)
+                | TraceFieldData::CreateResultAddress(vec)
+                | TraceFieldData::CallFrom(vec)
+                | TraceFieldData::CallTo(vec)
+                | TraceFieldData::CallInput(vec)
+                | TraceFieldData::CallSighash(vec)
+                | TraceFieldData::CallResultOutput(vec)
+                | TraceFieldData::SuicideAddress(vec)
+                | TraceFieldData::SuicideRefundAddress(vec)
+                | TraceFieldData::RewardAuthor(vec) => {
+                    columns.push(create_optional_hex_column(field, vec, binary)?)
+                }
+            },
+            Some(FieldData::StateDiffsData(data)) => match data {
+                StateDiffFieldData::TransactionIndex(vec) => columns.push(Series::new(field, vec)),
+                StateDiffFieldData::Kind(vec) => columns.push(Series::new(field, vec)),
+                StateDiffFieldData::Address(vec)
+                | StateDiffFieldData::Key(vec)
+                | StateDiffFieldData::Prev(vec)
+                | StateDiffFieldData::Next(vec) => {
+                    columns.push(create_optional_hex_column(field, vec, binary)?)
+                }
+            },
+
+            _ => panic!("{} not found", field),
+        }
+    }
+    Ok(columns)
+}
+
+//with `--binary`, hashes, addresses and calldata are written as bytes instead of 0x-prefixed hex
+fn create_hex_column(name: &str, values: &[String], binary: bool) -> Result<Series> {
+    if !binary {
+        return Ok(Series::new(name, values));
+    }
+    let bytes = values
+        .iter()
+        .map(|value| hex_to_bytes(value))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::msg(format!("Invalid hex in {}: {}", name, e)))?;
+    Ok(Series::new(name, bytes))
+}
+
+fn create_optional_hex_column(
+    name: &str,
+    values: &[Option<String>],
+    binary: bool,
+) -> Result<Series> {
+    if !binary {
+        return Ok(Series::new(name, values));
+    }
+    let bytes = values
+        .iter()
+        .map(|value| value.as_deref().map(hex_to_bytes).transpose())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::msg(format!("Invalid hex in {}: {}", name, e)))?;
+    Ok(Series::new(name, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_columns_from_field_data, create_field_data};
+    use crate::cli::config::{Dataset, U256Format};
+    use crate::models::{BlockHeader, Log, Scalar, StateDiff, Trace, Transaction};
+    use anyhow::Result;
+    use polars::prelude::{AnyValue, DataType};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_binary_columns() -> Result<()> {
+        let fields = ["address", "data", "logIndex", "blockNumber"];
+        let mut field_map = HashMap::new();
+        for field in fields {
+            field_map.insert(field.to_owned(), create_field_data(field, Dataset::Logs)?);
+        }
+        let log = Log {
+            log_index: Some(Scalar::Number(1)),
+            address: Some(Scalar::String("0x00ff".to_owned())),
+            data: Some(Scalar::String("0x".to_owned())),
+            ..Default::default()
+        };
+        let header = BlockHeader {
+            number: 17000000,
+            ..Default::default()
+        };
+        for data in field_map.values_mut() {
+            data.add_log(&header, &log)?;
+        }
+
+        let columns =
+            create_columns_from_field_data(&field_map, &fields, U256Format::String, true)?;
+        assert_eq!(columns[0].get(0)?, AnyValue::Binary(&[0x00, 0xff]));
+        assert_eq!(columns[1].get(0)?, AnyValue::Binary(&[]));
+        assert_eq!(columns[2].dtype(), &DataType::UInt64);
+        assert_eq!(columns[3].get(0)?, AnyValue::UInt64(17000000));
 
-        _ => panic!("{} not found", field),
-    });
-    columns
+        let columns =
+            create_columns_from_field_data(&field_map, &fields, U256Format::String, false)?;
+        assert_eq!(columns[0].get(0)?, AnyValue::String("0x00ff"));
+
+        let log = Log {
+            address: Some(Scalar::String("0xzz".to_owned())),
+            ..log
+        };
+        for data in field_map.values_mut() {
+            data.add_log(&header, &log)?;
+        }
+        assert!(
+            create_columns_from_field_data(&field_map, &fields, U256Format::String, true).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_trace_columns() -> Result<()> {
+        let fields = ["type", "callTo", "callValue", "callGas", "traceAddress"];
+        let mut field_map = HashMap::new();
+        for field in fields {
+            field_map.insert(field.to_owned(), create_field_data(field, Dataset::Traces)?);
+        }
+        let call = Trace {
+            trace_type: Some(Scalar::String("call".to_owned())),
+            call_to: Some(Scalar::String("0x00ff".to_owned())),
+            call_value: Some(Scalar::String("0x3635c9adc5dea00000".to_owned())),
+            call_gas: Some(Scalar::String("0x5208".to_owned())),
+            trace_address: Some(vec![0, 1]),
+            ..Default::default()
+        };
+        //call fields are missing from other trace types
+        let reward = Trace {
+            trace_type: Some(Scalar::String("reward".to_owned())),
+            trace_address: Some(vec![]),
+            ..Default::default()
+        };
+        for trace in [call, reward] {
+            for data in field_map.values_mut() {
+                data.add_trace(&trace)?;
+            }
+        }
+
+        let columns =
+            create_columns_from_field_data(&field_map, &fields, U256Format::String, false)?;
+        assert_eq!(columns[0].get(1)?, AnyValue::String("reward"));
+        assert_eq!(columns[1].get(0)?, AnyValue::String("0x00ff"));
+        assert_eq!(columns[1].get(1)?, AnyValue::Null);
+        assert_eq!(
+            columns[2].get(0)?,
+            AnyValue::String("1000000000000000000000")
+        );
+        assert_eq!(columns[3].get(0)?, AnyValue::UInt64(21000));
+        assert_eq!(columns[4].len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_diff_columns() -> Result<()> {
+        let fields = ["transactionIndex", "kind", "prev", "next"];
+        let mut field_map = HashMap::new();
+        for field in fields {
+            field_map.insert(
+                field.to_owned(),
+                create_field_data(field, Dataset::StateDiffs)?,
+            );
+        }
+        //a created slot has no previous value
+        let diff = StateDiff {
+            transaction_index: Some(Scalar::Number(4)),
+            kind: Some(Scalar::String("+".to_owned())),
+            next: Some(Scalar::String("0x01".to_owned())),
+            ..Default::default()
+        };
+        for data in field_map.values_mut() {
+            data.add_state_diff(&diff)?;
+        }
+
+        let columns =
+            create_columns_from_field_data(&field_map, &fields, U256Format::String, false)?;
+        assert_eq!(columns[0].get(0)?, AnyValue::UInt64(4));
+        assert_eq!(columns[1].get(0)?, AnyValue::String("+"));
+        assert_eq!(columns[2].get(0)?, AnyValue::Null);
+        assert_eq!(columns[3].get(0)?, AnyValue::String("0x01"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_v() -> Result<()> {
+        let mut data = create_field_data("v", Dataset::Transactions)?;
+        let header = BlockHeader::default();
+        for v in [Some("0x1b"), None] {
+            let tx = Transaction {
+                v: v.map(|v| Scalar::String(v.to_owned())),
+                ..Default::default()
+            };
+            data.add_transaction(&header, &tx)?;
+        }
+        //a missing v is null rather than a made up 0
+        let field_map = HashMap::from([("v".to_owned(), data)]);
+        let columns =
+            create_columns_from_field_data(&field_map, &["v"], U256Format::String, false)?;
+        assert_eq!(columns[0].get(0)?, AnyValue::UInt64(27));
+        assert_eq!(columns[0].get(1)?, AnyValue::Null);
+
+        let tx = Transaction {
+            v: Some(Scalar::String("0xzz".to_owned())),
+            ..Default::default()
+        };
+        let mut data = create_field_data("v", Dataset::Transactions)?;
+        assert!(data.add_transaction(&header, &tx).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_contract_creation_to() -> Result<()> {
+        let mut data = create_field_data("to", Dataset::Transactions)?;
+        let header = BlockHeader::default();
+        for to in [Some("0x00ff"), None] {
+            let tx = Transaction {
+                to: to.map(|to| Scalar::String(to.to_owned())),
+                ..Default::default()
+            };
+            data.add_transaction(&header, &tx)?;
+        }
+        //a contract creation has no recipient, not an empty one
+        let field_map = HashMap::from([("to".to_owned(), data)]);
+        let columns =
+            create_columns_from_field_data(&field_map, &["to"], U256Format::String, true)?;
+        assert_eq!(columns[0].get(0)?, AnyValue::Binary(&[0x00, 0xff]));
+        assert_eq!(columns[0].get(1)?, AnyValue::Null);
+        let columns =
+            create_columns_from_field_data(&field_map, &["to"], U256Format::String, false)?;
+        assert_eq!(columns[0].get(0)?, AnyValue::String("0x00ff"));
+        assert_eq!(columns[0].get(1)?, AnyValue::Null);
+        Ok(())
+    }
 }
@@ -0,0 +1,161 @@
+use crate::cli::config::{Compression, OutputFormat, ParquetOptions};
+use anyhow::Result;
+use polars::prelude::{
+    AnyValue, CsvWriter, DataFrame, DataType, GzipLevel, IpcWriter, NamedFrom, ParquetCompression,
+    ParquetWriter, SerWriter, Series, ZstdLevel,
+};
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub fn write_dataframe(
+    df: &mut DataFrame,
+    format: OutputFormat,
+    parquet: &ParquetOptions,
+    file: File,
+) -> Result<()> {
+    match format {
+        OutputFormat::Parquet => {
+            ParquetWriter::new(file)
+                .with_compression(get_parquet_compression(parquet.compression)?)
+                .with_row_group_size(parquet.row_group_size)
+                .with_statistics(parquet.statistics)
+                .finish(df)?;
+        }
+        OutputFormat::Csv => {
+            //csv has no nested, binary or decimal types: lists are written as json arrays,
+            //bytes as 0x-prefixed hex and decimals as digits
+            let mut df = DataFrame::new(
+                df.get_columns()
+                    .iter()
+                    .map(flatten_column)
+                    .collect::<Result<Vec<Series>>>()?,
+            )?;
+            CsvWriter::new(file).finish(&mut df)?;
+        }
+        OutputFormat::JsonLines => write_json_lines(df, file)?,
+        OutputFormat::ArrowIpc => IpcWriter::new(file).finish(df)?,
+    }
+    Ok(())
+}
+
+fn get_parquet_compression(compression: Compression) -> Result<ParquetCompression> {
+    Ok(match compression {
+        Compression::Uncompressed => ParquetCompression::Uncompressed,
+        Compression::Snappy => ParquetCompression::Snappy,
+        Compression::Gzip(level) => {
+            ParquetCompression::Gzip(level.map(GzipLevel::try_new).transpose()?)
+        }
+        Compression::Lz4 => ParquetCompression::Lz4Raw,
+        Compression::Zstd(level) => {
+            ParquetCompression::Zstd(level.map(ZstdLevel::try_new).transpose()?)
+        }
+    })
+}
+
+fn write_json_lines(df: &mut DataFrame, file: File) -> Result<()> {
+    df.as_single_chunk_par();
+    let mut writer = BufWriter::new(file);
+    let names = df.get_column_names();
+    let mut columns: Vec<_> = df.get_columns().iter().map(|s| s.iter()).collect();
+    for _ in 0..df.height() {
+        let row: Map<String, Value> = names
+            .iter()
+            .zip(columns.iter_mut())
+            .map(|(name, column)| {
+                let value = column.next().map_or(Value::Null, |v| any_value_to_json(&v));
+                (name.to_string(), value)
+            })
+            .collect();
+        serde_json::to_writer(&mut writer, &row)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn flatten_column(series: &Series) -> Result<Series> {
+    if !matches!(
+        series.dtype(),
+        DataType::List(_) | DataType::Binary | DataType::Decimal(..)
+    ) {
+        return Ok(series.clone());
+    }
+    let series = series.rechunk();
+    let values: Vec<Option<String>> = series
+        .iter()
+        .map(|v| match v {
+            AnyValue::Null => None,
+            AnyValue::Binary(v) => Some(format!("0x{}", hex::encode(v))),
+            v @ AnyValue::Decimal(..) => Some(v.to_string()),
+            v => Some(any_value_to_json(&v).to_string()),
+        })
+        .collect();
+    Ok(Series::new(series.name(), values))
+}
+
+fn any_value_to_json(value: &AnyValue) -> Value {
+    match value {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(v) => json!(v),
+        AnyValue::String(v) => json!(v),
+        AnyValue::StringOwned(v) => json!(v.as_str()),
+        AnyValue::UInt8(v) => json!(v),
+        AnyValue::UInt16(v) => json!(v),
+        AnyValue::UInt32(v) => json!(v),
+        AnyValue::UInt64(v) => json!(v),
+        AnyValue::Int8(v) => json!(v),
+        AnyValue::Int16(v) => json!(v),
+        AnyValue::Int32(v) => json!(v),
+        AnyValue::Int64(v) => json!(v),
+        AnyValue::Float32(v) => json!(v),
+        AnyValue::Float64(v) => json!(v),
+        AnyValue::Binary(v) => json!(format!("0x{}", hex::encode(v))),
+        AnyValue::BinaryOwned(v) => json!(format!("0x{}", hex::encode(v))),
+        AnyValue::List(series) => {
+            let series = series.rechunk();
+            Value::Array(series.iter().map(|v| any_value_to_json(&v)).collect())
+        }
+        v => json!(v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_dataframe;
+    use crate::cli::config::{OutputFormat, ParquetOptions};
+    use anyhow::Result;
+    use polars::prelude::{DataFrame, NamedFrom, Series};
+    use std::fs::{self, File};
+
+    #[test]
+    fn test_list_columns_in_text_formats() -> Result<()> {
+        let topics = Series::new(
+            "topics",
+            vec![Series::new("", &["0xa", "0xb"]), Series::new("", &["0xc"])],
+        );
+        let index = Series::new("logIndex", &[1u64, 2]);
+        let mut df = DataFrame::new(vec![index, topics])?;
+        let path = std::env::temp_dir().join("little_squid_formats_test");
+
+        let parquet = ParquetOptions::default();
+        write_dataframe(
+            &mut df,
+            OutputFormat::JsonLines,
+            &parquet,
+            File::create(&path)?,
+        )?;
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "{\"logIndex\":1,\"topics\":[\"0xa\",\"0xb\"]}\n{\"logIndex\":2,\"topics\":[\"0xc\"]}\n"
+        );
+
+        write_dataframe(&mut df, OutputFormat::Csv, &parquet, File::create(&path)?)?;
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "logIndex,topics\n1,\"[\"\"0xa\"\",\"\"0xb\"\"]\"\n2,\"[\"\"0xc\"\"]\"\n"
+        );
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -1,2 +1,6 @@
+pub mod abi;
 pub mod export;
 pub mod fields;
+pub mod formats;
+pub mod naming;
+pub mod u256;
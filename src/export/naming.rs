@@ -0,0 +1,141 @@
+use crate::cli::config::{Config, Partition};
+use crate::models::{Block, Scalar};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use utils::utils::timestamp_to_date;
+
+pub const DEFAULT_FILE_TEMPLATE: &str = "{network}/{dataset}_{start}-{end}";
+pub const DEFAULT_PARTITION_TEMPLATE: &str = "part-{start}-{end}";
+
+//path of an output file relative to the output dir, without the extension
+pub fn get_file_name(config: &Config, start_block: u64, end_block: u64) -> Result<String> {
+    let values = HashMap::from([
+        ("network", config.network.clone()),
+        ("dataset", config.get_table_name()),
+        ("start", start_block.to_string()),
+        ("end", end_block.to_string()),
+        ("fields_hash", config.fields_hash()),
+    ]);
+    render_template(&config.file_template, &values)
+}
+
+//hive-style directories, e.g. `ethereum-mainnet/dataset=logs/block_bucket=17000000`
+pub fn get_partition_dir(config: &Config, partition: Partition, block: &Block) -> Result<String> {
+    let number = block.header.number;
+    let key = match partition {
+        Partition::BlockBucket(size) => format!("block_bucket={}", number / size * size),
+        Partition::Date => {
+            let timestamp = block
+                .header
+                .timestamp
+                .as_ref()
+                .and_then(Scalar::as_f64)
+                .ok_or_else(|| anyhow!("Block {} has no timestamp", number))?;
+            format!("date={}", timestamp_to_date(timestamp as u64))
+        }
+    };
+    Ok(format!(
+        "{}/dataset={}/{}",
+        config.network,
+        config.get_table_name(),
+        key
+    ))
+}
+
+//replaces `{name}` placeholders, `{name:08}` pads the value with zeros to 8 characters
+pub fn render_template(template: &str, values: &HashMap<&str, String>) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed placeholder in template: {}", template))?
+            + open;
+        let placeholder = &rest[open + 1..close];
+        let (name, spec) = match placeholder.split_once(':') {
+            Some((name, spec)) => (name, Some(spec)),
+            None => (placeholder, None),
+        };
+        let value = values
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown placeholder in template: {{{}}}", name))?;
+        match spec {
+            None => rendered.push_str(value),
+            Some(spec) => {
+                let width = spec
+                    .strip_prefix('0')
+                    .and_then(|width| width.parse::<usize>().ok())
+                    .ok_or_else(|| anyhow!("Invalid padding in template: {{{}}}", placeholder))?;
+                rendered.push_str(&format!("{:0>width$}", value, width = width));
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_partition_dir, render_template};
+    use crate::cli::config::{Config, Partition};
+    use crate::cli::opts::Opts;
+    use crate::models::{Block, BlockHeader, Scalar};
+    use anyhow::Result;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_render_template() -> Result<()> {
+        let values = HashMap::from([
+            ("dataset", "logs".to_owned()),
+            ("start", "17000000".to_owned()),
+            ("end", "17004999".to_owned()),
+        ]);
+        assert_eq!(
+            render_template("{dataset}/{start:010}-{end}", &values)?,
+            "logs/0017000000-17004999"
+        );
+        assert!(render_template("{network}", &values).is_err());
+        assert!(render_template("{start:10}", &values).is_err());
+        assert!(render_template("{start", &values).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition_dir() -> Result<()> {
+        let config: Config = Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("1:10".to_owned()),
+            ..Default::default()
+        }
+        .try_into()?;
+        let block = |number: u64, timestamp: Option<u64>| Block {
+            header: BlockHeader {
+                number,
+                timestamp: timestamp.map(Scalar::Number),
+                ..Default::default()
+            },
+            transactions: vec![],
+            logs: vec![],
+            traces: vec![],
+            state_diffs: vec![],
+        };
+        let bucket = Partition::BlockBucket(1000000);
+        assert_eq!(
+            get_partition_dir(&config, bucket, &block(17123456, None))?,
+            "ethereum-mainnet/dataset=logs/block_bucket=17000000"
+        );
+        //the first block of a bucket
+        assert_eq!(
+            get_partition_dir(&config, bucket, &block(18000000, None))?,
+            "ethereum-mainnet/dataset=logs/block_bucket=18000000"
+        );
+        assert_eq!(
+            get_partition_dir(&config, Partition::Date, &block(17123456, Some(1682899200)))?,
+            "ethereum-mainnet/dataset=logs/date=2023-05-01"
+        );
+        assert!(get_partition_dir(&config, Partition::Date, &block(17123456, None)).is_err());
+        Ok(())
+    }
+}
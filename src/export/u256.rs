@@ -0,0 +1,122 @@
+use crate::cli::config::U256Format;
+use crate::models::Scalar;
+use anyhow::{anyhow, Result};
+use ethnum::U256;
+use polars::prelude::{BinaryChunked, Int128Chunked};
+use polars::prelude::{IntoSeries, NamedFrom, NewChunkedArray, Series};
+
+//largest precision of a Decimal128 column
+const DECIMAL_PRECISION: usize = 38;
+
+//quantities come as numbers when small, as 0x-prefixed hex otherwise
+pub fn parse_u256(value: &Scalar) -> Result<U256> {
+    match value {
+        Scalar::Number(value) => Ok(U256::from(*value)),
+        Scalar::String(value) if value == "0x" => Ok(U256::ZERO),
+        Scalar::String(value) => U256::from_str_prefixed(value)
+            .map_err(|e| anyhow!("Invalid 256-bit number {}: {}", value, e)),
+        Scalar::Float(value) => Err(anyhow!("Expected an integer, got {}", value)),
+    }
+}
+
+//hi/lo writes two columns, every other format one
+pub fn create_u256_columns(name: &str, values: &[Option<U256>], format: U256Format) -> Vec<Series> {
+    match format {
+        U256Format::String => {
+            let strings: Vec<Option<String>> = values
+                .iter()
+                .map(|value| value.map(|value| value.to_string()))
+                .collect();
+            vec![Series::new(name, strings)]
+        }
+        U256Format::Binary => {
+            let bytes: Vec<Option<Vec<u8>>> = values
+                .iter()
+                .map(|value| value.map(|value| value.to_be_bytes().to_vec()))
+                .collect();
+            vec![Series::new(name, bytes)]
+        }
+        U256Format::Decimal => {
+            let max = U256::from(10u8).pow(DECIMAL_PRECISION as u32);
+            let mut overflows = 0;
+            let decimals = values.iter().map(|value| match value {
+                Some(value) if *value < max => Some(value.as_i128()),
+                Some(_) => {
+                    overflows += 1;
+                    None
+                }
+                None => None,
+            });
+            let decimals = Int128Chunked::from_iter_options(name, decimals)
+                .into_decimal_unchecked(Some(DECIMAL_PRECISION), 0)
+                .into_series();
+            if overflows > 0 {
+                eprintln!(
+                    "{} values of {} do not fit in Decimal128 and were written as null",
+                    overflows, name
+                );
+            }
+            vec![decimals]
+        }
+        //polars has no 128-bit integers, so each half is 16 big-endian bytes
+        U256Format::HiLo => {
+            let (hi, lo): (Vec<_>, Vec<_>) = values
+                .iter()
+                .map(|value| match value {
+                    Some(value) => {
+                        let (hi, lo) = value.into_words();
+                        (Some(hi.to_be_bytes()), Some(lo.to_be_bytes()))
+                    }
+                    None => (None, None),
+                })
+                .unzip();
+            vec![
+                BinaryChunked::from_iter_options(&format!("{}_hi", name), hi.into_iter())
+                    .into_series(),
+                BinaryChunked::from_iter_options(&format!("{}_lo", name), lo.into_iter())
+                    .into_series(),
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_u256_columns, parse_u256};
+    use crate::cli::config::U256Format;
+    use crate::models::Scalar;
+    use anyhow::Result;
+    use ethnum::U256;
+    use polars::prelude::{AnyValue, DataType};
+
+    #[test]
+    fn test_u256_columns() -> Result<()> {
+        //1e21 wei overflows u64
+        let wei = parse_u256(&Scalar::String("0x3635c9adc5dea00000".to_owned()))?;
+        assert_eq!(wei.to_string(), "1000000000000000000000");
+        assert_eq!(parse_u256(&Scalar::Number(7))?, U256::from(7u8));
+        assert!(parse_u256(&Scalar::String("0xzz".to_owned())).is_err());
+
+        let values = [Some(wei), None, Some(U256::MAX)];
+        let strings = create_u256_columns("value", &values, U256Format::String);
+        assert_eq!(
+            strings[0].get(0)?,
+            AnyValue::String("1000000000000000000000")
+        );
+        assert_eq!(strings[0].get(1)?, AnyValue::Null);
+
+        let binary = create_u256_columns("value", &values, U256Format::Binary);
+        assert_eq!(binary[0].get(0)?, AnyValue::Binary(&wei.to_be_bytes()));
+
+        let decimals = create_u256_columns("value", &values, U256Format::Decimal);
+        assert_eq!(decimals[0].dtype(), &DataType::Decimal(Some(38), Some(0)));
+        assert_eq!(decimals[0].null_count(), 2);
+
+        let hilo = create_u256_columns("value", &values, U256Format::HiLo);
+        assert_eq!(hilo[0].name(), "value_hi");
+        assert_eq!(hilo[1].name(), "value_lo");
+        assert_eq!(hilo[0].get(0)?, AnyValue::Binary(&[0; 16]));
+        assert_eq!(hilo[1].get(2)?, AnyValue::Binary(&[0xff; 16]));
+        Ok(())
+    }
+}
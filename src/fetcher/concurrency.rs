@@ -0,0 +1,189 @@
+use crate::fetcher::retry::{classify, Failure};
+use anyhow::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+pub const DEFAULT_CONCURRENCY: usize = 10;
+pub const DEFAULT_MAX_CONCURRENCY: usize = 64;
+//a request slower than this many times the fastest one seen means the archive is struggling
+const LATENCY_TOLERANCE: u32 = 3;
+//one burst of errors only halves the limit once
+const DECREASE_COOLDOWN: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Concurrency {
+    Fixed(usize),
+    //additive increase, multiplicative decrease between 1 and `max`
+    Adaptive { max: usize },
+}
+
+/// Limits the number of chunks fetched at once. In adaptive mode the limit
+/// grows by one after a full limit's worth of healthy requests and is halved
+/// when the archive throttles or times out.
+pub struct ConcurrencyLimiter {
+    concurrency: Concurrency,
+    semaphore: Arc<Semaphore>,
+    state: Mutex<State>,
+}
+
+struct State {
+    limit: usize,
+    //permits to drop instead of returning, after a decrease
+    to_forget: usize,
+    successes: usize,
+    fastest: Option<Duration>,
+    last_decrease: Option<Instant>,
+}
+
+pub struct Permit {
+    permit: Option<OwnedSemaphorePermit>,
+    limiter: Arc<ConcurrencyLimiter>,
+}
+
+impl Concurrency {
+    pub fn get_name(&self) -> String {
+        match self {
+            Concurrency::Fixed(limit) => limit.to_string(),
+            Concurrency::Adaptive { max } => format!("auto (up to {})", max),
+        }
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(concurrency: Concurrency) -> ConcurrencyLimiter {
+        let limit = match concurrency {
+            Concurrency::Fixed(limit) => limit,
+            Concurrency::Adaptive { max } => DEFAULT_CONCURRENCY.min(max),
+        };
+        ConcurrencyLimiter {
+            concurrency,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            state: Mutex::new(State {
+                limit,
+                to_forget: 0,
+                successes: 0,
+                fastest: None,
+                last_decrease: None,
+            }),
+        }
+    }
+
+    pub async fn acquire(self: &Arc<Self>) -> Permit {
+        //the semaphore is never closed
+        let permit = self.semaphore.clone().acquire_owned().await.unwrap();
+        Permit {
+            permit: Some(permit),
+            limiter: self.clone(),
+        }
+    }
+
+    pub fn get_limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    pub fn record_success(&self, latency: Duration) {
+        let max = match self.concurrency {
+            Concurrency::Adaptive { max } => max,
+            Concurrency::Fixed(_) => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        let fastest = *state.fastest.get_or_insert(latency);
+        state.fastest = Some(fastest.min(latency));
+        if latency > fastest * LATENCY_TOLERANCE {
+            state.successes = 0;
+            return;
+        }
+        state.successes += 1;
+        if state.successes >= state.limit && state.limit < max {
+            state.successes = 0;
+            state.limit += 1;
+            match state.to_forget {
+                0 => self.semaphore.add_permits(1),
+                _ => state.to_forget -= 1,
+            }
+        }
+    }
+
+    pub fn record_error(&self, error: &Error) {
+        if !matches!(self.concurrency, Concurrency::Adaptive { .. }) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.successes = 0;
+        let is_timeout = error
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|error| error.is_timeout());
+        if !is_timeout && !matches!(classify(error), Failure::Throttled(_)) {
+            return;
+        }
+        if state
+            .last_decrease
+            .is_some_and(|last| last.elapsed() < DECREASE_COOLDOWN)
+        {
+            return;
+        }
+        let limit = (state.limit / 2).max(1);
+        state.to_forget += state.limit - limit;
+        state.limit = limit;
+        state.last_decrease = Some(Instant::now());
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap();
+        if state.to_forget > 0 {
+            state.to_forget -= 1;
+            if let Some(permit) = self.permit.take() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Concurrency, ConcurrencyLimiter};
+    use crate::fetcher::retry::HttpError;
+    use crate::fetcher::workers::ArchiveClient;
+    use anyhow::{Error, Result};
+    use reqwest::StatusCode;
+    use std::time::Duration;
+
+    #[test]
+    fn test_adaptive_limit() {
+        let limiter = ConcurrencyLimiter::new(Concurrency::Adaptive { max: 11 });
+        assert_eq!(limiter.get_limit(), 10);
+        for _ in 0..30 {
+            limiter.record_success(Duration::from_millis(100));
+        }
+        assert_eq!(limiter.get_limit(), 11);
+
+        let throttled = Error::new(HttpError {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: None,
+            body: String::new(),
+        });
+        limiter.record_error(&throttled);
+        assert_eq!(limiter.get_limit(), 5);
+        //still cooling down
+        limiter.record_error(&throttled);
+        assert_eq!(limiter.get_limit(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_halves_limit() -> Result<()> {
+        //connections are queued by the os but never answered
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/height", listener.local_addr()?);
+        let client = ArchiveClient::new(Duration::from_millis(100))?;
+        let timeout = Error::new(client.http.get(url).send().await.unwrap_err());
+
+        let limiter = ConcurrencyLimiter::new(Concurrency::Adaptive { max: 64 });
+        assert_eq!(limiter.get_limit(), 10);
+        limiter.record_error(&timeout);
+        assert_eq!(limiter.get_limit(), 5);
+        Ok(())
+    }
+}
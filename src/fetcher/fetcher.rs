@@ -7,145 +7,135 @@ use tracing::debug;
 
 use crate::cli::{
     self,
-    config::{Dataset, Range},
+    config::{get_context_field, Dataset, DatasetConfig, Range},
 };
+use crate::fetcher::concurrency::ConcurrencyLimiter;
+use crate::fetcher::retry::{classify, Failure, HttpError};
+use crate::fetcher::workers::ArchiveClient;
+use crate::manifest::Manifest;
+use crate::models::Block;
+use crate::report::{FailedRange, FailureReport};
+use crate::save::WriteHandle;
 use anyhow::Result;
 use serde_json::{json, Map, Value};
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::{self, sync::Semaphore};
-use utils::{archive::get_worker, utils::sizeof_val};
-
-const MAX_CHUNK_SIZE: usize = 10 * 1024 * 1024;
+use std::{sync::Arc, time::Instant};
+use utils::archive::{get_height, get_worker};
 
+//one query selecting every dataset of the run
 fn create_query_json(
-    dataset: Dataset,
+    datasets: &[DatasetConfig],
     start_block: u64,
     end_block: u64,
-    fields: &[String],
-    options: &HashMap<String, Vec<String>>,
+    header_fields: &[&str],
 ) -> Value {
-    let field_map = fields
-        .iter()
-        .map(|field| (field.clone(), json!(true)))
-        .collect::<Map<_, _>>();
-
-    match dataset {
-        Dataset::Blocks => json!({
-            "fields": {"block": field_map},
-            "fromBlock": start_block,
-            "includeAllBlocks": true,
-        }),
-
-        Dataset::Transactions => {
-            let options_json = json!(options);
-            json!({
-                "transactions": [options_json],
-                "fields": {
-                    "block": {},
-                    "transaction": field_map
-                },
-                "fromBlock": start_block,
-                "toBlock": end_block,
-                "includeAllBlocks": true,
-            })
-        }
+    //the archive range is inclusive, ours excludes end_block
+    let to_block = end_block - 1;
+    //block header fields needed for the output layout, on top of the dataset fields
+    let mut field_maps: Map<String, Value> = Map::new();
+    field_maps.insert(
+        "block".to_owned(),
+        json!(header_fields
+            .iter()
+            .map(|field| (field.to_string(), json!(true)))
+            .collect::<Map<_, _>>()),
+    );
+    let mut query = json!({
+        "fromBlock": start_block,
+        "toBlock": to_block,
+        "includeAllBlocks": true,
+    });
 
-        Dataset::Logs => {
-            let options_json = json!(options);
-            json!({
-                "logs": [options_json],
-                "fields": {
-                    "block": {},
-                    "transaction": {},
-                    "log": field_map
-                },
-                "fromBlock": start_block,
-                "includeAllBlocks": true,
-            })
+    for dataset in datasets {
+        //block context columns come from the header
+        let field_map = dataset
+            .fields
+            .iter()
+            .filter(|field| get_context_field(dataset.dataset, field).is_none())
+            .map(|field| (field.clone(), json!(true)));
+        let (request, entity) = match dataset.dataset {
+            Dataset::Blocks => (None, "block"),
+            Dataset::Transactions => (Some("transactions"), "transaction"),
+            Dataset::Logs => (Some("logs"), "log"),
+            Dataset::Traces => (Some("traces"), "trace"),
+            Dataset::StateDiffs => (Some("stateDiffs"), "stateDiff"),
+        };
+        //each selection is a request of its own, the archive returns rows matching any of them
+        if let (Some(request), false) = (request, dataset.related) {
+            let mut selections: Vec<Value> = dataset.options.iter().map(|s| json!(s)).collect();
+            if selections.is_empty() {
+                selections.push(json!({}));
+            }
+            //included datasets are filled with the rows related to the selected ones
+            let relation = match dataset.dataset {
+                Dataset::Logs => Some((Dataset::Transactions, "transaction")),
+                Dataset::Transactions => Some((Dataset::Logs, "logs")),
+                _ => None,
+            };
+            if let Some((related, flag)) = relation {
+                if datasets.iter().any(|d| d.related && d.dataset == related) {
+                    for selection in &mut selections {
+                        selection[flag] = json!(true);
+                    }
+                }
+            }
+            query[request] = json!(selections);
+        }
+        let entry = field_maps
+            .entry(entity)
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .expect("field selection is an object");
+        entry.extend(field_map);
+        if dataset.dataset == Dataset::Logs {
+            field_maps.entry("transaction").or_insert_with(|| json!({}));
         }
     }
+    query["fields"] = Value::Object(field_maps);
+    query
+}
+
+//blocks of one archive response
+pub struct Page {
+    pub blocks: Vec<Block>,
+    //where the next request starts
+    pub next_block: u64,
+    //bytes of the response body
+    pub size: u64,
 }
 
 pub async fn fetch_block_chunk(
-    dataset: Dataset,
+    config: &Config,
+    worker: &str,
     start_block: u64,
     end_block: u64,
-    fields: &[String],
-    options: &HashMap<String, Vec<String>>,
     client: &Client,
-) -> Result<(Vec<Value>, u64), Error> {
-    let block_query = create_query_json(dataset, start_block, end_block, fields, options);
-
-    let worker = get_worker(
-        "https://v2.archive.subsquid.io/network/ethereum-mainnet",
-        &start_block.to_string(),
-    )
-    .await?;
+) -> Result<Page, Error> {
+    let block_query = create_query_json(
+        &config.datasets,
+        start_block,
+        end_block,
+        &config.header_fields(),
+    );
 
-    let result = client
-        .post(worker)
-        .json(&block_query)
-        .send()
-        .await?
-        .text()
-        .await?;
+    let response = client.post(worker).json(&block_query).send().await?;
+    if !response.status().is_success() {
+        return Err(HttpError::from_response(response).await.into());
+    }
+    let result = response.bytes().await?;
 
-    let blocks_value: Value = serde_json::from_str(&result)
+    let blocks: Vec<Block> = serde_json::from_slice(&result)
         .map_err(|e| Error::msg(format!("Error parsing JSON: {}", e)))?;
 
-    let blocks = match blocks_value.as_array() {
-        Some(blocks) => blocks,
-        None => {
-            //println!("Error fetching");
-            return Err(Error::msg("Invalid JSON format: Expected an array"));
-        }
-    };
-
-    let next_block = blocks
+    let last_block = blocks
         .last()
-        .and_then(|b| b["header"]["number"].as_u64())
-        .ok_or_else(|| {
-            Error::msg("Invalid block data format: 'number' field missing or not a u64")
-        })?;
+        .map(|b| b.header.number)
+        .ok_or_else(|| Error::msg("Invalid JSON format: Expected a non-empty array"))?;
     //println!("Fetched {:?} blocks from {:?}", blocks.len(), start_block);
-    if blocks.len() == 1 {}
-    Ok((blocks.to_vec(), next_block))
-}
-
-fn send_blocks(
-    blocks: &[Value],
-    write_tx: Sender<Vec<Value>>,
-    //stats_tx: &Sender<u64>,
-) -> Result<(), Error> {
-    let mut data_chunk = Vec::new();
-    let mut current_size = 0;
-    for data in blocks {
-        let chunk_size = sizeof_val(data);
-        if current_size + chunk_size >= MAX_CHUNK_SIZE {
-            //println!("Sending chunk of size {:?}", data_chunk.len());
-            write_tx
-                .send(data_chunk.clone())
-                .map_err(|e| Error::msg(format!("Error sending blocks: {}", e)))?;
-            data_chunk.drain(..);
-            current_size = 0;
-        }
-        //println!("ADDING BLOCKS {:?}", blocks.len());
-        current_size += chunk_size;
-        // stats_tx
-        //     .send(blocks.len() as u64)
-        //     .map_err(|e| Error::msg(format!("Error sending stats: {}", e)))?;
-        data_chunk.push(data.clone());
-    }
-
-    // Send any remaining data
-    if !data_chunk.is_empty() {
-        //println!("222Sending chunk of size {:?}", data_chunk.len());
-        write_tx
-            .send(data_chunk)
-            .map_err(|e| Error::msg(format!("Error sending blocks: {}", e)))?;
-    }
-
-    Ok(())
+    Ok(Page {
+        blocks,
+        next_block: last_block + 1,
+        size: result.len() as u64,
+    })
 }
 
 fn compute_chunk_ranges(total_range: &Range, chunk_size: u64) -> Vec<Range> {
@@ -159,122 +149,786 @@ fn compute_chunk_ranges(total_range: &Range, chunk_size: u64) -> Vec<Range> {
         .collect()
 }
 
+//splits chunks at multiples of blocks_per_file so every chunk belongs to a single output file
+fn split_at_file_boundaries(ranges: Vec<Range>, blocks_per_file: u64) -> Vec<Range> {
+    let mut split = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let mut start = range.start;
+        while start < range.end {
+            let end = ((start / blocks_per_file + 1) * blocks_per_file).min(range.end);
+            split.push(Range { start, end });
+            start = end;
+        }
+    }
+    split
+}
+
 pub async fn fetch(
     config: Config,
-
-    write_tx: Sender<Vec<Value>>,
+    manifest: Manifest,
+    limiter: Arc<ConcurrencyLimiter>,
+    writer: WriteHandle,
     stats_tx: Sender<u64>,
+) -> Result<Vec<FailedRange>, Error> {
+    let client = Arc::new(ArchiveClient::new(config.request_timeout)?);
+    let mut failed = fetch_block_ranges(
+        &config,
+        &manifest,
+        client.clone(),
+        limiter.clone(),
+        &writer,
+        &stats_tx,
+    )
+    .await?;
+    FailureReport::save(&config, &manifest, &failed)?;
+    if config.follow {
+        follow_head(
+            &config,
+            &manifest,
+            client,
+            limiter,
+            &writer,
+            &stats_tx,
+            &mut failed,
+        )
+        .await?;
+    }
+    Ok(failed)
+}
+
+//keeps polling the archive height and fetches blocks as they get confirmed
+async fn follow_head(
+    config: &Config,
+    manifest: &Manifest,
+    client: Arc<ArchiveClient>,
+    limiter: Arc<ConcurrencyLimiter>,
+    writer: &WriteHandle,
+    stats_tx: &Sender<u64>,
+    failed: &mut Vec<FailedRange>,
 ) -> Result<(), Error> {
-    let client = Arc::new(reqwest::Client::new());
-    let semaphore = Arc::new(Semaphore::new(10)); // Adjust concurrency level
-    fetch_block_ranges(&config, client, semaphore, &write_tx, &stats_tx).await?;
-    Ok(())
+    let mut next_block = config.range.end;
+    loop {
+        tokio::time::sleep(config.poll_interval).await;
+        //a proxy error page is as temporary as a network error
+        let height = match get_height(&config.archive_url).await {
+            Ok(height) => match height.trim().parse::<u64>() {
+                Ok(height) => height,
+                Err(e) => {
+                    eprintln!("Invalid archive height {:?}: {}", height.trim(), e);
+                    continue;
+                }
+            },
+            Err(e) => {
+                eprintln!("Error fetching archive height: {:?}", e);
+                continue;
+            }
+        };
+        let range = match get_tail_range(next_block, height, config.confirmations) {
+            Some(range) => range,
+            None => continue,
+        };
+
+        let tail_config = Config {
+            range: range.clone(),
+            retry_ranges: None,
+            ..config.clone()
+        };
+        let tail_failed = fetch_block_ranges(
+            &tail_config,
+            manifest,
+            client.clone(),
+            limiter.clone(),
+            writer,
+            stats_tx,
+        )
+        .await?;
+        if !tail_failed.is_empty() {
+            failed.extend(tail_failed);
+            FailureReport::save(config, manifest, failed)?;
+        }
+        println!("Fetched blocks {}-{}", range.start, range.end - 1);
+        next_block = range.end;
+    }
+}
+
+//the confirmed blocks from next_block on, none until the head has moved past it
+fn get_tail_range(next_block: u64, height: u64, confirmations: u64) -> Option<Range> {
+    let head = height.saturating_sub(confirmations);
+    if head < next_block {
+        return None;
+    }
+    Some(Range {
+        start: next_block,
+        end: head + 1,
+    })
 }
 
 pub async fn fetch_block_ranges(
     config: &Config,
-    client: Arc<Client>,
-    semaphore: Arc<Semaphore>,
-    write_tx: &Sender<Vec<Value>>,
+    manifest: &Manifest,
+    client: Arc<ArchiveClient>,
+    limiter: Arc<ConcurrencyLimiter>,
+    writer: &WriteHandle,
     stats_tx: &Sender<u64>,
-) -> Result<(), Error> {
-    let ranges = compute_chunk_ranges(&config.range, config.dataset.get_chunk_size());
+) -> Result<Vec<FailedRange>, Error> {
+    let mut chunk_ranges: Vec<Range> = config
+        .get_ranges()
+        .iter()
+        .flat_map(|range| compute_chunk_ranges(range, config.get_chunk_size()))
+        .collect();
+    if let Some(blocks_per_file) = config.blocks_per_file {
+        chunk_ranges = split_at_file_boundaries(chunk_ranges, blocks_per_file);
+    }
+    let (ranges, skipped) = get_missing_ranges(config, manifest, chunk_ranges);
+    if skipped > 0 {
+        println!("Resuming: skipping {} blocks already fetched", skipped);
+        let _ = stats_tx.send(skipped);
+    }
 
     let tasks: Vec<_> = ranges
-        .into_iter()
+        .iter()
+        .cloned()
         .map(|range| {
-            let semaphore_clone = semaphore.clone();
+            let limiter_clone = limiter.clone();
             let client_clone = client.clone();
             let config_clone = config.clone();
-            let write_tx_clone = write_tx.clone();
+            let writer_clone = writer.clone();
             let stats_tx_clone = stats_tx.clone();
 
             tokio::spawn(async move {
-                let _permit = semaphore_clone.acquire_owned().await.unwrap();
+                let _permit = limiter_clone.acquire().await;
                 match fetch_sized_chunk(
                     &config_clone,
                     client_clone,
-                    range.start,
-                    range.end,
-                    &write_tx_clone,
-                    //&stats_tx_clone,
+                    &limiter_clone,
+                    &range,
+                    &writer_clone,
                 )
                 .await
                 {
                     Ok(_) => {
-                        stats_tx_clone.send(range.end - range.start).unwrap();
+                        //the progress bar is gone once the range is done, e.g. when following
+                        let _ = stats_tx_clone.send(range.end - range.start);
                         if range.end == config_clone.range.end {
                             debug!("Finished fetching all blocks");
-                            stats_tx_clone.send(0).unwrap(); // Signal the end of the stream
+                            let _ = stats_tx_clone.send(0); // Signal the end of the stream
                         }
+                        None
+                    }
+                    Err(failed) => {
+                        eprintln!("Error fetching block range: {}", failed.error);
+                        Some(failed)
                     }
-                    Err(e) => eprintln!("Error fetching block range: {:?}", e),
                 }
-
-                Result::<(), Error>::Ok(())
             })
         })
         .collect();
 
-    let _results = join_all(tasks).await;
-    Ok(())
+    let failed: Vec<FailedRange> = join_all(tasks)
+        .await
+        .into_iter()
+        .zip(ranges)
+        .filter_map(|(result, range)| match result {
+            Ok(failed) => failed,
+            Err(e) => Some(FailedRange {
+                start: range.start,
+                end: range.end,
+                error: e.to_string(),
+            }),
+        })
+        .collect();
+    Ok(match config.blocks_per_file {
+        Some(blocks_per_file) => widen_to_files(config, failed, blocks_per_file),
+        None => failed,
+    })
 }
 
-pub async fn fetch_sized_chunk(
+//the chunks still to fetch and the number of blocks skipped
+fn get_missing_ranges(
     config: &Config,
-    client: Arc<Client>,
-    start_block: u64,
-    end_block: u64,
-    write_tx: &Sender<Vec<Value>>,
-    //stats_tx: &Sender<u64>,
-) -> Result<(), Error> {
-    let mut current_start = start_block;
-    let max_attempts = 3;
-    let mut attempt = 0;
-    let mut backoff = Duration::from_millis(100);
-    let mut fetched_blocks = Vec::new();
-    while current_start < end_block {
-        if attempt >= max_attempts {
-            eprintln!(
-                "Max retry attempts reached for blocks starting at {}",
-                current_start
-            );
-
-            break; // Skip to the next range after max retries
+    manifest: &Manifest,
+    chunk_ranges: Vec<Range>,
+) -> (Vec<Range>, u64) {
+    let mut missing = Vec::with_capacity(chunk_ranges.len());
+    let mut skipped = 0;
+    for range in chunk_ranges {
+        match config.blocks_per_file {
+            //a file is rewritten as a whole, so only skip chunks whose file is complete
+            Some(blocks_per_file) => {
+                let file = Range {
+                    start: (range.start / blocks_per_file * blocks_per_file)
+                        .max(config.range.start),
+                    end: ((range.start / blocks_per_file + 1) * blocks_per_file)
+                        .min(config.range.end),
+                };
+                if manifest.is_completed(&file) {
+                    skipped += range.end - range.start;
+                } else {
+                    missing.push(range);
+                }
+            }
+            //blocks written before a failure or a crash are not fetched again
+            None => {
+                let parts = manifest.get_missing(&range);
+                skipped +=
+                    range.end - range.start - parts.iter().map(|r| r.end - r.start).sum::<u64>();
+                missing.extend(parts);
+            }
+        }
+    }
+    (missing, skipped)
+}
+
+//the writer drops incomplete files, so a retry has to fetch the whole file again
+fn widen_to_files(
+    config: &Config,
+    failed: Vec<FailedRange>,
+    blocks_per_file: u64,
+) -> Vec<FailedRange> {
+    let mut widened: Vec<FailedRange> = Vec::with_capacity(failed.len());
+    for failure in failed {
+        let index = failure.start / blocks_per_file;
+        let start = (index * blocks_per_file).max(config.range.start);
+        match widened.iter().find(|f| f.start == start) {
+            Some(_) => continue,
+            None => widened.push(FailedRange {
+                start,
+                end: ((index + 1) * blocks_per_file).min(config.range.end),
+                error: failure.error,
+            }),
         }
+    }
+    widened.sort_by_key(|f| f.start);
+    widened
+}
 
-        match fetch_block_chunk(
-            config.dataset.clone(),
-            current_start,
-            end_block,
-            &config.fields,
-            &config.options,
-            &client,
-        )
+//streams the pages of a range to the writer, a failure reports the blocks not fetched yet
+pub async fn fetch_sized_chunk(
+    config: &Config,
+    client: Arc<ArchiveClient>,
+    limiter: &ConcurrencyLimiter,
+    range: &Range,
+    writer: &WriteHandle,
+) -> Result<(), FailedRange> {
+    let mut current_start = range.start;
+    let result = fetch_pages(config, &client, limiter, range, writer, &mut current_start).await;
+    if let Err(e) = result {
+        //the pages already written are kept
+        if current_start > range.start {
+            let done = Range {
+                start: range.start,
+                end: current_start,
+            };
+            let _ = writer.send_done(done).await;
+        }
+        return Err(FailedRange {
+            start: current_start,
+            end: range.end,
+            error: format!("{:#}", e),
+        });
+    }
+    writer
+        .send_done(range.clone())
         .await
-        {
-            Ok((blocks, next_block)) => {
+        .map_err(|e| FailedRange {
+            start: range.start,
+            end: range.end,
+            error: e.to_string(),
+        })
+}
+
+async fn fetch_pages(
+    config: &Config,
+    client: &ArchiveClient,
+    limiter: &ConcurrencyLimiter,
+    range: &Range,
+    writer: &WriteHandle,
+    cursor: &mut u64,
+) -> Result<(), Error> {
+    let policy = config.retry;
+    let end_block = range.end;
+    let mut attempt = 0;
+    let mut worker: Option<String> = None;
+    //a reused worker may no longer serve the next block, that is not a failed attempt
+    let mut reused = false;
+    while *cursor < end_block {
+        let current_start = *cursor;
+        let started = Instant::now();
+        let page = match &worker {
+            Some(worker) => {
+                fetch_block_chunk(config, worker, current_start, end_block, &client.http).await
+            }
+            None => match client.workers.get(current_start) {
+                Some(url) => {
+                    worker = Some(url);
+                    reused = true;
+                    continue;
+                }
+                None => match get_worker(
+                    &client.http,
+                    &config.archive_url,
+                    &current_start.to_string(),
+                )
+                .await
+                {
+                    Ok(url) => {
+                        client.workers.insert(&url, current_start);
+                        worker = Some(url);
+                        reused = false;
+                        continue;
+                    }
+                    Err(e) => Err(e.into()),
+                },
+            },
+        };
+        match page {
+            Ok(page) => {
+                limiter.record_success(started.elapsed());
+                if let Some(worker) = &worker {
+                    client.workers.extend(worker, page.next_block);
+                }
                 //println!("Fetched {:?} blocks from {:?}", blocks.len(), current_start);
-                fetched_blocks.extend(blocks);
-                // let _ = send_blocks(&fetched_blocks, write_tx.clone(), stats_tx);
-                current_start = next_block;
+                writer.send_blocks(range, page.blocks, page.size).await?;
+                *cursor = page.next_block;
                 attempt = 0; // Reset attempts after a successful fetch
-                backoff = Duration::from_millis(100); // Reset backoff
+                reused = true;
             }
             Err(e) => {
+                let failure = classify(&e);
+                if let Some(url) = worker.take() {
+                    //a reused worker was only assumed to serve the block, whatever it answers
+                    //but throttling is checked with a freshly assigned worker before counting
+                    let stale = reused && !matches!(failure, Failure::Throttled(_));
+                    if stale || failure == Failure::Worker {
+                        client.workers.evict(&url);
+                    }
+                    if stale {
+                        continue;
+                    }
+                }
+                limiter.record_error(&e);
+                attempt += 1;
+                let backoff = match failure {
+                    //the pages before the failure are kept, the rest of the range is reported
+                    Failure::Query => {
+                        return Err(e.context(format!(
+                            "Query for blocks starting at {} was rejected",
+                            current_start
+                        )))
+                    }
+                    Failure::Throttled(retry_after) => retry_after.map_or_else(
+                        || policy.get_backoff(attempt),
+                        |delay| delay + policy.get_jitter(),
+                    ),
+                    Failure::Worker => policy.get_backoff(attempt),
+                };
+                if attempt >= policy.max_attempts {
+                    return Err(e.context(format!(
+                        "Max retry attempts reached for blocks starting at {}",
+                        current_start
+                    )));
+                }
                 eprintln!(
                     "Error fetching blocks: {:?}, retrying in {:?}...",
                     e, backoff
                 );
                 tokio::time::sleep(backoff).await;
-                attempt += 1;
-                backoff *= 2; // Exponential backoff
             }
         }
     }
-    // println!("Fetched {} blocks, sending", fetched_blocks.len());
+    Ok(())
+}
 
-    let _ = send_blocks(&fetched_blocks, write_tx.clone());
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_query_json, fetch_pages, get_missing_ranges, get_tail_range,
+        split_at_file_boundaries, widen_to_files,
+    };
+    use crate::cli::config::{Config, Dataset, DatasetConfig, Range, Selection};
+    use crate::cli::opts::Opts;
+    use crate::fetcher::concurrency::{Concurrency, ConcurrencyLimiter};
+    use crate::fetcher::workers::ArchiveClient;
+    use crate::manifest::Manifest;
+    use crate::report::FailedRange;
+    use crate::save::{MemoryBudget, WriteHandle, WriteMessage};
+    use anyhow::Result;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
 
-    Ok(())
+    fn get_config(name: &str, range: &str, blocks_per_file: Option<u64>) -> Result<Config> {
+        let output_dir = std::env::temp_dir().join(format!("little_squid_test_{}", name));
+        let _ = std::fs::remove_dir_all(&output_dir);
+        Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some(range.to_owned()),
+            output_dir: Some(output_dir.to_string_lossy().into_owned()),
+            blocks_per_file,
+            ..Default::default()
+        }
+        .try_into()
+    }
+
+    #[test]
+    fn test_resume_partly_written_chunk() -> Result<()> {
+        let config = get_config("resume_partly_written_chunk", "0:100", None)?;
+        let mut manifest = Manifest::load(&config)?;
+        //the first pages of the second chunk were written before it failed
+        manifest.mark_completed(Range { start: 0, end: 50 })?;
+        manifest.mark_completed(Range { start: 50, end: 70 })?;
+        let chunks = vec![
+            Range { start: 0, end: 50 },
+            Range {
+                start: 50,
+                end: 100,
+            },
+        ];
+        let (missing, skipped) = get_missing_ranges(&config, &manifest, chunks);
+        assert_eq!(
+            missing,
+            vec![Range {
+                start: 70,
+                end: 100
+            }]
+        );
+        assert_eq!(skipped, 70);
+        Ok(())
+    }
+
+    fn range(start: u64, end: u64) -> Range {
+        Range { start, end }
+    }
+
+    fn failure(start: u64, end: u64) -> FailedRange {
+        FailedRange {
+            start,
+            end,
+            error: "error".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_split_at_file_boundaries() {
+        //an unaligned start, a chunk spanning two files and a range smaller than a file
+        let chunks = vec![range(5, 25), range(25, 28), range(32, 35)];
+        assert_eq!(
+            split_at_file_boundaries(chunks, 10),
+            vec![
+                range(5, 10),
+                range(10, 20),
+                range(20, 25),
+                range(25, 28),
+                range(32, 35)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_widen_to_files() -> Result<()> {
+        let config = get_config("widen_to_files", "5:95", Some(10))?;
+        let failed = vec![
+            failure(6, 8),
+            failure(12, 14),
+            failure(15, 18),
+            failure(91, 93),
+        ];
+        let widened = widen_to_files(&config, failed, 10);
+        //the first file starts with the range, the last one ends with it
+        assert_eq!(
+            widened.iter().map(|f| f.get_range()).collect::<Vec<_>>(),
+            vec![range(5, 10), range(10, 20), range(90, 95)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_files() -> Result<()> {
+        let config = get_config("resume_files", "5:95", Some(10))?;
+        let mut manifest = Manifest::load(&config)?;
+        manifest.mark_completed(range(5, 10))?;
+        //a file whose chunks were only partly written is fetched again as a whole
+        manifest.mark_completed(range(10, 15))?;
+        manifest.mark_completed(range(90, 95))?;
+        let chunks = split_at_file_boundaries(vec![range(5, 25), range(85, 95)], 10);
+        let (missing, skipped) = get_missing_ranges(&config, &manifest, chunks);
+        assert_eq!(missing, vec![range(10, 20), range(20, 25), range(85, 90)]);
+        assert_eq!(skipped, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_selection_groups() {
+        let selection = |address: &str, topic0: &str| -> Selection {
+            [
+                ("address".to_owned(), vec![address.to_owned()]),
+                ("topic0".to_owned(), vec![topic0.to_owned()]),
+            ]
+            .into()
+        };
+        let logs = DatasetConfig {
+            dataset: Dataset::Logs,
+            fields: vec!["address".to_owned(), "data".to_owned()],
+            options: vec![selection("0xa", "0xt"), selection("0xb", "0xs")],
+            related: false,
+        };
+        let query = create_query_json(&[logs], 10, 20, &[]);
+        assert_eq!(
+            query,
+            json!({
+                "fromBlock": 10,
+                "toBlock": 19,
+                "includeAllBlocks": true,
+                "logs": [
+                    {"address": ["0xa"], "topic0": ["0xt"]},
+                    {"address": ["0xb"], "topic0": ["0xs"]},
+                ],
+                "fields": {
+                    "block": {},
+                    "transaction": {},
+                    "log": {"address": true, "data": true},
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_query_related_datasets() {
+        let dataset = |dataset: Dataset, field: &str, related: bool| DatasetConfig {
+            dataset,
+            fields: vec![field.to_owned()],
+            //included datasets take no options of their own
+            options: match (related, dataset) {
+                (true, _) => vec![],
+                (false, Dataset::Logs) => {
+                    vec![[("address".to_owned(), vec!["0xa".to_owned()])].into()]
+                }
+                (false, _) => vec![[("to".to_owned(), vec!["0xa".to_owned()])].into()],
+            },
+            related,
+        };
+        //parent transactions of the selected logs
+        let query = create_query_json(
+            &[
+                dataset(Dataset::Logs, "data", false),
+                dataset(Dataset::Transactions, "hash", true),
+            ],
+            0,
+            10,
+            &[],
+        );
+        assert_eq!(
+            query["logs"],
+            json!([{"address": ["0xa"], "transaction": true}])
+        );
+        assert_eq!(query.get("transactions"), None);
+        assert_eq!(
+            query["fields"],
+            json!({"block": {}, "log": {"data": true}, "transaction": {"hash": true}})
+        );
+
+        //logs of the selected transactions
+        let query = create_query_json(
+            &[
+                dataset(Dataset::Transactions, "hash", false),
+                dataset(Dataset::Logs, "data", true),
+            ],
+            0,
+            10,
+            &[],
+        );
+        assert_eq!(
+            query["transactions"],
+            json!([{"to": ["0xa"], "logs": true}])
+        );
+        assert_eq!(query.get("logs"), None);
+
+        //without an included dataset the selections are left alone
+        let query = create_query_json(&[dataset(Dataset::Logs, "data", false)], 0, 10, &[]);
+        assert_eq!(query["logs"], json!([{"address": ["0xa"]}]));
+    }
+
+    #[test]
+    fn test_query_traces() {
+        let traces = DatasetConfig {
+            dataset: Dataset::Traces,
+            fields: vec!["type".to_owned(), "callTo".to_owned()],
+            options: vec![[("type".to_owned(), vec!["call".to_owned()])].into()],
+            related: false,
+        };
+        let query = create_query_json(&[traces], 0, 10, &["timestamp"]);
+        assert_eq!(query["traces"], json!([{"type": ["call"]}]));
+        assert_eq!(
+            query["fields"],
+            json!({
+                "block": {"timestamp": true},
+                "trace": {"type": true, "callTo": true},
+            })
+        );
+        assert_eq!(query["includeAllBlocks"], json!(true));
+    }
+
+    #[test]
+    fn test_query_state_diffs() {
+        let state_diffs = DatasetConfig {
+            dataset: Dataset::StateDiffs,
+            fields: vec!["key".to_owned(), "next".to_owned()],
+            options: vec![],
+            related: false,
+        };
+        let query = create_query_json(&[state_diffs], 0, 10, &[]);
+        //no options selects every diff
+        assert_eq!(query["stateDiffs"], json!([{}]));
+        assert_eq!(
+            query["fields"],
+            json!({"block": {}, "stateDiff": {"key": true, "next": true}})
+        );
+    }
+
+    #[test]
+    fn test_tail_range() {
+        //height 110 with 10 confirmations makes block 100 the newest one to fetch
+        assert_eq!(get_tail_range(95, 110, 10), Some(range(95, 101)));
+        assert_eq!(get_tail_range(100, 110, 10), Some(range(100, 101)));
+        //the head has not moved past the blocks already fetched
+        assert_eq!(get_tail_range(101, 110, 10), None);
+        //more confirmations than blocks
+        assert_eq!(get_tail_range(0, 5, 10), Some(range(0, 1)));
+        assert_eq!(get_tail_range(1, 5, 10), None);
+        //the next poll starts where the last range ended
+        let next = get_tail_range(95, 110, 10).unwrap().end;
+        assert_eq!(get_tail_range(next, 112, 10), Some(range(101, 103)));
+    }
+
+    //a local archive answering each request line, e.g. "POST /worker", with a status and a body
+    fn serve(listener: TcpListener, routes: Vec<(String, u16, String)>) -> Arc<Mutex<Vec<String>>> {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                //the headers, then as much body as they announce
+                let length = loop {
+                    let read = stream.read(&mut buf).await.unwrap_or(0);
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let body = text
+                            .lines()
+                            .find_map(|line| {
+                                line.to_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap_or(0))
+                            })
+                            .unwrap_or(0);
+                        break end + 4 + body;
+                    }
+                    if read == 0 {
+                        break 0;
+                    }
+                };
+                while request.len() < length {
+                    let read = stream.read(&mut buf).await.unwrap_or(0);
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let text = String::from_utf8_lossy(&request).into_owned();
+                let line = text.lines().next().unwrap_or_default();
+                let line = line.trim_end_matches(" HTTP/1.1").to_owned();
+                let (status, body) = routes
+                    .iter()
+                    .find(|(route, _, _)| *route == line)
+                    .map(|(_, status, body)| (*status, body.clone()))
+                    .unwrap_or((404, String::new()));
+                log.lock().unwrap().push(line);
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        requests
+    }
+
+    //the cached worker of the previous chunk does not serve this one and rejects the query
+    #[tokio::test]
+    async fn test_stale_worker_is_reassigned() -> Result<()> {
+        let mut config = get_config("stale_worker_is_reassigned", "0:30", None)?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        config.archive_url = url.clone();
+        let requests = serve(
+            listener,
+            vec![
+                (
+                    "POST /stale".to_owned(),
+                    400,
+                    "Unknown block range".to_owned(),
+                ),
+                ("GET /0/worker".to_owned(), 200, format!("{}/fresh", url)),
+                (
+                    "POST /fresh".to_owned(),
+                    200,
+                    json!([{"header": {"number": 9}}]).to_string(),
+                ),
+                ("GET /20/worker".to_owned(), 200, format!("{}/bad", url)),
+                ("POST /bad".to_owned(), 400, "Invalid query".to_owned()),
+            ],
+        );
+        let client = ArchiveClient::new(config.request_timeout)?;
+        client.workers.insert(&format!("{}/stale", url), 0);
+        let limiter = ConcurrencyLimiter::new(Concurrency::Fixed(1));
+        let (write_tx, mut write_rx) = tokio::sync::mpsc::channel(16);
+        let writer = WriteHandle::new(write_tx, Arc::new(MemoryBudget::new(config.memory_budget)));
+
+        let mut cursor = 0;
+        fetch_pages(
+            &config,
+            &client,
+            &limiter,
+            &range(0, 10),
+            &writer,
+            &mut cursor,
+        )
+        .await?;
+        assert_eq!(cursor, 10);
+        assert!(matches!(
+            write_rx.try_recv()?,
+            WriteMessage::Blocks { blocks, .. } if blocks.len() == 1
+        ));
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["POST /stale", "GET /0/worker", "POST /fresh"]
+        );
+
+        //a freshly assigned worker rejecting the query fails right away
+        let mut cursor = 20;
+        let error = fetch_pages(
+            &config,
+            &client,
+            &limiter,
+            &range(20, 30),
+            &writer,
+            &mut cursor,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("was rejected"),
+            "{:#}",
+            error
+        );
+        assert_eq!(
+            requests.lock().unwrap()[3..],
+            ["GET /20/worker", "POST /bad"]
+        );
+        Ok(())
+    }
 }
@@ -1,2 +1,4 @@
+pub mod concurrency;
 pub mod fetcher;
-
+pub mod retry;
+pub mod workers;
@@ -0,0 +1,159 @@
+use anyhow::Error;
+use rand::Rng;
+use reqwest::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+/// How a chunk request is retried: the backoff doubles from 200ms up to
+/// `max_backoff`, and up to `jitter` of random delay is added to every wait.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub max_backoff: Duration,
+    pub jitter: Duration,
+}
+
+//what to do about a failed request
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    //429 or 503, wait as long as the archive asks
+    Throttled(Option<Duration>),
+    //any other 4xx, the query is wrong and retrying does not help
+    Query,
+    //the worker is unreachable or sent something unusable, ask for another one
+    Worker,
+}
+
+//non-success response from the archive or one of its workers
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: StatusCode,
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP {}: {}", self.status, self.body.trim())
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl HttpError {
+    pub async fn from_response(response: reqwest::Response) -> HttpError {
+        let status = response.status();
+        //only the delay-seconds form, an http date falls back to the backoff
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let body = response.text().await.unwrap_or_default();
+        HttpError {
+            status,
+            retry_after,
+            body,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 10,
+            max_backoff: Duration::from_secs(30),
+            jitter: Duration::from_millis(500),
+        }
+    }
+}
+
+impl RetryPolicy {
+    //delay before the given attempt, counting from 1
+    pub fn get_backoff(&self, attempt: u32) -> Duration {
+        let backoff = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        backoff + self.get_jitter()
+    }
+
+    pub fn get_jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+    }
+}
+
+pub fn classify(error: &Error) -> Failure {
+    let status = match error.downcast_ref::<HttpError>() {
+        Some(error) => match error.status {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                return Failure::Throttled(error.retry_after)
+            }
+            status => Some(status),
+        },
+        None => error
+            .downcast_ref::<reqwest::Error>()
+            .and_then(|error| error.status()),
+    };
+    match status {
+        Some(StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) => {
+            Failure::Throttled(None)
+        }
+        Some(status) if status.is_client_error() => Failure::Query,
+        _ => Failure::Worker,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, Failure, HttpError, RetryPolicy};
+    use anyhow::Error;
+    use reqwest::StatusCode;
+    use std::time::Duration;
+
+    fn http_error(status: StatusCode, retry_after: Option<u64>) -> Error {
+        Error::new(HttpError {
+            status,
+            retry_after: retry_after.map(Duration::from_secs),
+            body: String::new(),
+        })
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(
+            classify(&http_error(StatusCode::TOO_MANY_REQUESTS, Some(5))),
+            Failure::Throttled(Some(Duration::from_secs(5)))
+        );
+        assert_eq!(
+            classify(&http_error(StatusCode::SERVICE_UNAVAILABLE, None)),
+            Failure::Throttled(None)
+        );
+        assert_eq!(
+            classify(&http_error(StatusCode::BAD_REQUEST, None)),
+            Failure::Query
+        );
+        assert_eq!(
+            classify(&http_error(StatusCode::BAD_GATEWAY, None)),
+            Failure::Worker
+        );
+        assert_eq!(classify(&Error::msg("Invalid JSON")), Failure::Worker);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            max_backoff: Duration::from_secs(1),
+            jitter: Duration::ZERO,
+        };
+        assert_eq!(policy.get_backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.get_backoff(3), Duration::from_millis(800));
+        assert_eq!(policy.get_backoff(9), Duration::from_secs(1));
+    }
+}
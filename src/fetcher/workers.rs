@@ -0,0 +1,110 @@
+use anyhow::Result;
+use reqwest::Client;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+//the archive reassigns data between workers, so assignments are looked up again after this
+pub const WORKER_TTL: Duration = Duration::from_secs(300);
+
+/// Http client for the archive together with the workers it has assigned,
+/// shared by every fetch task.
+pub struct ArchiveClient {
+    pub http: Client,
+    pub workers: WorkerCache,
+}
+
+/// Worker urls by the block ranges they are known to serve, so consecutive
+/// pages skip the worker lookup.
+pub struct WorkerCache {
+    ttl: Duration,
+    entries: Mutex<Vec<WorkerEntry>>,
+}
+
+struct WorkerEntry {
+    url: String,
+    start: u64,
+    //the first block not fetched from this worker yet, it is assumed to serve it as well
+    end: u64,
+    expires: Instant,
+}
+
+impl ArchiveClient {
+    //without a timeout a stuck request would hold its concurrency permit forever
+    pub fn new(timeout: Duration) -> Result<ArchiveClient> {
+        Ok(ArchiveClient {
+            http: Client::builder().timeout(timeout).build()?,
+            workers: WorkerCache::new(WORKER_TTL),
+        })
+    }
+}
+
+impl WorkerCache {
+    pub fn new(ttl: Duration) -> WorkerCache {
+        WorkerCache {
+            ttl,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn get(&self, block: u64) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|entry| entry.expires > now);
+        entries
+            .iter()
+            .find(|entry| entry.start <= block && block <= entry.end)
+            .map(|entry| entry.url.clone())
+    }
+
+    pub fn insert(&self, url: &str, block: u64) {
+        self.entries.lock().unwrap().push(WorkerEntry {
+            url: url.to_owned(),
+            start: block,
+            end: block,
+            expires: Instant::now() + self.ttl,
+        });
+    }
+
+    //records that the worker served every block before next_block
+    pub fn extend(&self, url: &str, next_block: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .iter_mut()
+            .filter(|entry| entry.url == url && entry.start < next_block)
+            .max_by_key(|entry| entry.start);
+        if let Some(entry) = entry {
+            entry.end = entry.end.max(next_block);
+        }
+    }
+
+    pub fn evict(&self, url: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|entry| entry.url != url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WorkerCache;
+    use std::time::Duration;
+
+    #[test]
+    fn test_worker_cache() {
+        let workers = WorkerCache::new(Duration::from_secs(60));
+        workers.insert("http://worker-1", 100);
+        workers.extend("http://worker-1", 150);
+        assert_eq!(workers.get(120).as_deref(), Some("http://worker-1"));
+        assert_eq!(workers.get(150).as_deref(), Some("http://worker-1"));
+        assert_eq!(workers.get(151), None);
+        assert_eq!(workers.get(99), None);
+
+        workers.evict("http://worker-1");
+        assert_eq!(workers.get(120), None);
+
+        let expired = WorkerCache::new(Duration::ZERO);
+        expired.insert("http://worker-2", 100);
+        assert_eq!(expired.get(100), None);
+    }
+}
@@ -1,5 +1,8 @@
 pub mod cli;
 pub mod export;
 pub mod fetcher;
+pub mod manifest;
+pub mod models;
 pub mod progress;
+pub mod report;
 pub mod save;
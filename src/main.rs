@@ -4,33 +4,52 @@ use clap::Parser;
 use crossbeam::channel::unbounded;
 use little_squid_cli::cli::config::Config;
 use little_squid_cli::cli::opts::Opts;
+use little_squid_cli::cli::summaries::print_summary;
+use little_squid_cli::fetcher::concurrency::ConcurrencyLimiter;
 use little_squid_cli::fetcher::fetcher;
+use little_squid_cli::manifest::Manifest;
 use little_squid_cli::progress::stats;
-use little_squid_cli::save;
+use little_squid_cli::report::FailureReport;
+use little_squid_cli::save::{self, MemoryBudget, WriteHandle, WRITE_CHANNEL_CAPACITY};
+use std::sync::Arc;
 use std::thread;
 use tokio;
 #[tokio::main]
 async fn main() -> Result<()> {
-    let config: Config = Opts::parse().try_into().unwrap();
-    let fields = config.fields.clone();
+    let config = Config::from_opts(Opts::parse()).await?;
+    let manifest = Manifest::load(&config)?;
     //let start_time = std::time::Instant::now();
 
-    let (write_tx, write_rx) = unbounded();
+    let budget = Arc::new(MemoryBudget::new(config.memory_budget));
+    let (write_tx, write_rx) = tokio::sync::mpsc::channel(WRITE_CHANNEL_CAPACITY);
 
     let (stat_tx, stat_rx) = unbounded();
-    let read_handle = tokio::spawn(fetcher::fetch(config.clone(), write_tx, stat_tx));
-    let stats_handle =
-        thread::spawn(move || stats::stats_loop(stat_rx, config.range.end - config.range.start));
+    let total_blocks = config.get_ranges().iter().map(|r| r.end - r.start).sum();
+    let summary_config = config.clone();
+    let report_path = FailureReport::get_path(&manifest);
+    let limiter = Arc::new(ConcurrencyLimiter::new(config.concurrency));
+    let read_handle = tokio::spawn(fetcher::fetch(
+        config.clone(),
+        manifest.clone(),
+        limiter.clone(),
+        WriteHandle::new(write_tx, budget.clone()),
+        stat_tx,
+    ));
+    let stats_handle = thread::spawn(move || stats::stats_loop(stat_rx, total_blocks, limiter));
 
-    let write_handle = thread::spawn(move || save::write_loop(config.dataset, fields, write_rx));
+    let write_handle = thread::spawn(move || save::write_loop(config, manifest, write_rx, budget));
 
     let read_io_result = read_handle.await?;
     let stats_io_result = stats_handle.join().unwrap();
     let write_io_result = write_handle.join().unwrap();
     //return error if any thread returned error
-    read_io_result?;
+    let failed = read_io_result?;
     stats_io_result?;
     write_io_result?;
+    print_summary(&summary_config, &failed, &report_path);
+    if !failed.is_empty() {
+        std::process::exit(1);
+    }
     //let elapsed_time = start_time.elapsed();
 
     //println!("\n Elapsed time: {:?}", elapsed_time);
@@ -0,0 +1,248 @@
+use crate::cli::config::{Config, DatasetConfig, Range};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use utils::utils::fnv1a_hash;
+
+/// Records which block ranges have been fetched and written for a given
+/// dataset, fields and options, so an interrupted run can be resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub network: String,
+    pub dataset: String,
+    pub format: String,
+    pub file_template: String,
+    pub partition: Option<String>,
+    pub blocks_per_file: Option<u64>,
+    pub fields: Vec<String>,
+    pub options: Vec<BTreeMap<String, Vec<String>>>,
+    pub completed: Vec<Range>,
+    //`--blocks-per-file` files cut at the edge of the range, replaced once more of them is written
+    #[serde(default)]
+    pub partial_files: Vec<Range>,
+    #[serde(skip)]
+    path: PathBuf,
+    //hash of everything that shapes the output, also in the manifest's file name
+    #[serde(skip)]
+    key: String,
+}
+
+impl Manifest {
+    pub fn load(config: &Config) -> Result<Manifest> {
+        let dataset = config.get_datasets_name();
+        //with several datasets, `logs.address` tells their fields and options apart
+        let qualify = |dataset: &DatasetConfig, name: &str| match config.datasets.len() {
+            1 => name.to_owned(),
+            _ => format!("{}.{}", dataset.dataset.get_name(), name),
+        };
+        //fields come out of a HashSet, sort them so reruns match
+        let mut fields: Vec<String> = config
+            .datasets
+            .iter()
+            .flat_map(|dataset| dataset.fields.iter().map(|field| qualify(dataset, field)))
+            .collect();
+        fields.sort();
+        let options: Vec<BTreeMap<String, Vec<String>>> = config
+            .datasets
+            .iter()
+            .flat_map(|dataset| {
+                dataset.options.iter().map(move |selection| {
+                    selection
+                        .iter()
+                        .map(|(key, values)| {
+                            let mut values = values.clone();
+                            values.sort();
+                            (qualify(dataset, key), values)
+                        })
+                        .collect()
+                })
+            })
+            .collect();
+
+        let network = config.network.clone();
+        let format = config.format.get_name().to_owned();
+        let file_template = config.file_template.clone();
+        let partition = config.partition.map(|partition| partition.get_name());
+        let blocks_per_file = config.blocks_per_file;
+        let u256 = config.u256.get_name().to_owned();
+        let binary = config.binary;
+        let related: Vec<&str> = config
+            .datasets
+            .iter()
+            .filter(|dataset| dataset.related)
+            .map(|dataset| dataset.dataset.get_name())
+            .collect();
+        let key = serde_json::to_string(&(
+            &network,
+            &dataset,
+            &format,
+            &file_template,
+            &partition,
+            &blocks_per_file,
+            &u256,
+            &binary,
+            &related,
+            &config.events,
+            &fields,
+            &options,
+        ))?;
+        let key = format!("{:016x}", fnv1a_hash(key.as_bytes()));
+        let path = config
+            .output_dir
+            .join(&network)
+            .join(format!("{}_{}.manifest.json", dataset, key));
+
+        if path.exists() {
+            let mut manifest: Manifest = serde_json::from_str(&fs::read_to_string(&path)?)?;
+            manifest.path = path;
+            manifest.key = key;
+            return Ok(manifest);
+        }
+
+        Ok(Manifest {
+            network,
+            dataset,
+            format,
+            file_template,
+            partition,
+            blocks_per_file,
+            fields,
+            options,
+            completed: Vec::new(),
+            partial_files: Vec::new(),
+            path,
+            key,
+        })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get_key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn is_completed(&self, range: &Range) -> bool {
+        self.completed
+            .iter()
+            .any(|done| done.start <= range.start && range.end <= done.end)
+    }
+
+    //parts of the range not covered yet, a range written in several files is partly covered after a crash
+    pub fn get_missing(&self, range: &Range) -> Vec<Range> {
+        let mut missing = Vec::new();
+        let mut start = range.start;
+        //completed ranges are sorted and merged
+        for done in &self.completed {
+            if done.end <= start || done.start >= range.end {
+                continue;
+            }
+            if done.start > start {
+                missing.push(Range {
+                    start,
+                    end: done.start,
+                });
+            }
+            start = start.max(done.end);
+        }
+        if start < range.end {
+            missing.push(Range {
+                start,
+                end: range.end,
+            });
+        }
+        missing
+    }
+
+    //partial files within the bounds, they are forgotten until written again
+    pub fn take_partial_files(&mut self, bounds: &Range) -> Vec<Range> {
+        let (taken, kept) = self
+            .partial_files
+            .drain(..)
+            .partition(|file| bounds.start <= file.start && file.end <= bounds.end);
+        self.partial_files = kept;
+        taken
+    }
+
+    //saved with the next completed range
+    pub fn add_partial_file(&mut self, bounds: Range) {
+        self.partial_files.push(bounds);
+    }
+
+    pub fn mark_completed(&mut self, range: Range) -> Result<()> {
+        self.completed.push(range);
+        self.merge_ranges();
+        self.save()
+    }
+
+    fn merge_ranges(&mut self) {
+        self.completed.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range> = Vec::with_capacity(self.completed.len());
+        for range in self.completed.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.completed = merged;
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(folder) = self.path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        //write to a temp file first so a crash never leaves a truncated manifest
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+    use crate::cli::config::Range;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_merged_ranges_cover_chunks() {
+        let mut manifest = Manifest {
+            network: "ethereum-mainnet".to_owned(),
+            dataset: "logs".to_owned(),
+            format: "parquet".to_owned(),
+            file_template: "{network}/{dataset}_{start}-{end}".to_owned(),
+            partition: None,
+            blocks_per_file: None,
+            fields: vec![],
+            options: Vec::new(),
+            completed: vec![
+                Range { start: 10, end: 20 },
+                Range { start: 0, end: 10 },
+                Range { start: 30, end: 40 },
+            ],
+            partial_files: Vec::new(),
+            path: PathBuf::new(),
+            key: String::new(),
+        };
+        manifest.merge_ranges();
+
+        assert_eq!(
+            manifest.completed,
+            vec![Range { start: 0, end: 20 }, Range { start: 30, end: 40 }]
+        );
+        assert!(manifest.is_completed(&Range { start: 5, end: 15 }));
+        assert!(!manifest.is_completed(&Range { start: 15, end: 35 }));
+        //a chunk written up to a failure or crash is only fetched from there on
+        assert_eq!(
+            manifest.get_missing(&Range { start: 15, end: 50 }),
+            vec![Range { start: 20, end: 30 }, Range { start: 40, end: 50 }]
+        );
+        assert!(manifest
+            .get_missing(&Range { start: 0, end: 20 })
+            .is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+use std::fmt;
+
+/// A block as returned by the archive, with only the items of the requested
+/// dataset filled in.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Block {
+    pub header: BlockHeader,
+    #[serde(default)]
+    pub transactions: Vec<Transaction>,
+    #[serde(default)]
+    pub logs: Vec<Log>,
+    #[serde(default)]
+    pub traces: Vec<Trace>,
+    #[serde(default, rename = "stateDiffs")]
+    pub state_diffs: Vec<StateDiff>,
+}
+
+//fields that were not requested are simply missing, so everything but the number is optional
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockHeader {
+    pub number: u64,
+    pub hash: Option<Scalar>,
+    pub parent_hash: Option<Scalar>,
+    pub timestamp: Option<Scalar>,
+    pub miner: Option<Scalar>,
+    pub state_root: Option<Scalar>,
+    pub transactions_root: Option<Scalar>,
+    pub receipts_root: Option<Scalar>,
+    pub gas_used: Option<Scalar>,
+    pub extra_data: Option<Scalar>,
+    pub base_fee_per_gas: Option<Scalar>,
+    pub logs_bloom: Option<Scalar>,
+    pub total_difficulty: Option<Scalar>,
+    pub size: Option<Scalar>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transaction {
+    pub id: Option<Scalar>,
+    pub transaction_index: Option<Scalar>,
+    pub from: Option<Scalar>,
+    pub to: Option<Scalar>,
+    pub hash: Option<Scalar>,
+    pub gas: Option<Scalar>,
+    pub gas_price: Option<Scalar>,
+    pub max_fee_per_gas: Option<Scalar>,
+    pub max_priority_fee_per_gas: Option<Scalar>,
+    pub input: Option<Scalar>,
+    pub nonce: Option<Scalar>,
+    pub value: Option<Scalar>,
+    pub v: Option<Scalar>,
+    pub r: Option<Scalar>,
+    pub s: Option<Scalar>,
+    pub y_parity: Option<Scalar>,
+    pub chain_id: Option<Scalar>,
+    pub gas_used: Option<Scalar>,
+    pub cumulative_gas_used: Option<Scalar>,
+    pub effective_gas_price: Option<Scalar>,
+    pub contract_address: Option<Scalar>,
+    #[serde(rename = "type")]
+    pub tx_type: Option<Scalar>,
+    pub status: Option<Scalar>,
+    pub sighash: Option<Scalar>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    pub id: Option<Scalar>,
+    pub log_index: Option<Scalar>,
+    pub transaction_index: Option<Scalar>,
+    pub transaction_hash: Option<Scalar>,
+    pub address: Option<Scalar>,
+    pub data: Option<Scalar>,
+    pub topics: Option<Vec<String>>,
+}
+
+//fields that do not apply to the trace type are missing
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trace {
+    pub transaction_index: Option<Scalar>,
+    pub trace_address: Option<Vec<u64>>,
+    pub subtraces: Option<Scalar>,
+    #[serde(rename = "type")]
+    pub trace_type: Option<Scalar>,
+    pub error: Option<Scalar>,
+    pub revert_reason: Option<Scalar>,
+    pub create_from: Option<Scalar>,
+    pub create_value: Option<Scalar>,
+    pub create_gas: Option<Scalar>,
+    pub create_init: Option<Scalar>,
+    pub create_result_gas_used: Option<Scalar>,
+    pub create_result_code: Option<Scalar>,
+    pub create_result_address: Option<Scalar>,
+    pub call_from: Option<Scalar>,
+    pub call_to: Option<Scalar>,
+    pub call_value: Option<Scalar>,
+    pub call_gas: Option<Scalar>,
+    pub call_input: Option<Scalar>,
+    pub call_sighash: Option<Scalar>,
+    pub call_type: Option<Scalar>,
+    pub call_result_gas_used: Option<Scalar>,
+    pub call_result_output: Option<Scalar>,
+    pub suicide_address: Option<Scalar>,
+    pub suicide_refund_address: Option<Scalar>,
+    pub suicide_balance: Option<Scalar>,
+    pub reward_author: Option<Scalar>,
+    pub reward_value: Option<Scalar>,
+    pub reward_type: Option<Scalar>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateDiff {
+    pub transaction_index: Option<Scalar>,
+    pub address: Option<Scalar>,
+    pub key: Option<Scalar>,
+    pub kind: Option<Scalar>,
+    pub prev: Option<Scalar>,
+    pub next: Option<Scalar>,
+}
+
+/// A single json value. The archive sends small integers as numbers and
+/// quantities as hex strings, the column builders convert them as needed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scalar {
+    Number(u64),
+    Float(f64),
+    String(String),
+}
+
+impl Scalar {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Scalar::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Scalar::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Scalar::Number(value) => Some(*value as f64),
+            Scalar::Float(value) => Some(*value),
+            Scalar::String(_) => None,
+        }
+    }
+}
+
+struct ScalarVisitor;
+
+impl<'de> Visitor<'de> for ScalarVisitor {
+    type Value = Scalar;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number or a string")
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Scalar, E> {
+        Ok(Scalar::Number(value as u64))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Scalar, E> {
+        Ok(Scalar::Number(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Scalar, E> {
+        Ok(match u64::try_from(value) {
+            Ok(value) => Scalar::Number(value),
+            Err(_) => Scalar::Float(value as f64),
+        })
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Scalar, E> {
+        Ok(Scalar::Float(value))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Scalar, E> {
+        Ok(Scalar::String(value.to_owned()))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Scalar, E> {
+        Ok(Scalar::String(value))
+    }
+}
+
+impl<'de> Deserialize<'de> for Scalar {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Scalar, D::Error> {
+        deserializer.deserialize_any(ScalarVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Block, Scalar};
+    use anyhow::Result;
+
+    #[test]
+    fn test_deserialize_block() -> Result<()> {
+        let blocks: Vec<Block> = serde_json::from_str(
+            r#"[{
+                "header": {"number": 17000000, "timestamp": 1681338455, "gasUsed": "0x1c9c380"},
+                "logs": [{"logIndex": 3, "address": "0xabc", "data": "0x", "topics": ["0x01"]}],
+                "traces": [{"type": "call", "traceAddress": [0, 1], "callTo": null}]
+            }]"#,
+        )?;
+        let block = &blocks[0];
+        assert_eq!(block.header.number, 17000000);
+        assert_eq!(block.header.timestamp, Some(Scalar::Number(1681338455)));
+        assert_eq!(
+            block.header.gas_used.as_ref().and_then(Scalar::as_str),
+            Some("0x1c9c380")
+        );
+        assert_eq!(block.logs[0].log_index, Some(Scalar::Number(3)));
+        assert_eq!(block.logs[0].topics, Some(vec!["0x01".to_owned()]));
+        assert_eq!(block.traces[0].trace_address, Some(vec![0, 1]));
+        assert_eq!(block.traces[0].call_to, None);
+        assert!(block.transactions.is_empty());
+        Ok(())
+    }
+}
@@ -1,16 +1,22 @@
+use crate::fetcher::concurrency::ConcurrencyLimiter;
 use anyhow::Result;
 use crossbeam::channel::Receiver;
 use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::Arc;
 use std::thread;
 use utils::utils::get_percentage;
 
-pub fn stats_loop(stats_rx: Receiver<u64>, total_blocks: u64) -> Result<()> {
+pub fn stats_loop(
+    stats_rx: Receiver<u64>,
+    total_blocks: u64,
+    limiter: Arc<ConcurrencyLimiter>,
+) -> Result<()> {
     //let progress = Arc::new(Mutex::new((0, false))); // (progress, completed)
     let progress_bar = ProgressBar::new(100);
 
     let progress_bar_style = ProgressStyle::default_bar()
         .template(
-            "[{elapsed_precise}] {spinner:.green} [{wide_bar:.cyan/blue}] {pos}/{len} ({eta})",
+            "[{elapsed_precise}] {spinner:.green} [{wide_bar:.cyan/blue}] {pos}/{len} ({eta}) {msg}",
         )
         .unwrap();
     progress_bar.set_style(progress_bar_style);
@@ -35,6 +41,7 @@ pub fn stats_loop(stats_rx: Receiver<u64>, total_blocks: u64) -> Result<()> {
         }
         //let (current_progress, completed) = *progress.lock().unwrap();
         progress_bar.set_position(normalized_progress as u64);
+        progress_bar.set_message(format!("concurrency {}", limiter.get_limit()));
 
         thread::sleep(std::time::Duration::from_millis(100));
     }
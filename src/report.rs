@@ -0,0 +1,124 @@
+use crate::cli::config::{Config, Range};
+use crate::manifest::Manifest;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Block ranges that could not be fetched, written next to the manifest so
+/// they can be fetched again with `--retry-failed <report>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailureReport {
+    pub network: String,
+    pub dataset: String,
+    //manifest key of the run, a retry has to write the same kind of files
+    pub key: String,
+    pub range: Range,
+    pub failed: Vec<FailedRange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailedRange {
+    pub start: u64,
+    pub end: u64,
+    pub error: String,
+}
+
+impl FailedRange {
+    pub fn get_range(&self) -> Range {
+        Range {
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+impl FailureReport {
+    pub fn load(path: &Path) -> Result<FailureReport> {
+        let report = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Cannot read failure report {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&report)?)
+    }
+
+    pub fn get_path(manifest: &Manifest) -> PathBuf {
+        manifest
+            .get_path()
+            .with_extension("")
+            .with_extension("failed.json")
+    }
+
+    //writes the report, or removes a stale one once nothing is failing
+    pub fn save(config: &Config, manifest: &Manifest, failed: &[FailedRange]) -> Result<()> {
+        let path = FailureReport::get_path(manifest);
+        if failed.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        let report = FailureReport {
+            network: config.network.clone(),
+            dataset: config.get_datasets_name(),
+            key: manifest.get_key().to_owned(),
+            range: config.range.clone(),
+            failed: failed.to_vec(),
+        };
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FailedRange, FailureReport};
+    use crate::cli::config::{Config, Range};
+    use crate::cli::opts::Opts;
+    use crate::manifest::Manifest;
+    use anyhow::Result;
+
+    fn get_opts(output_dir: &str, fields: &[&str], retry_failed: Option<String>) -> Opts {
+        Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("0:100".to_owned()),
+            fields: Some(fields.iter().map(|field| field.to_string()).collect()),
+            output_dir: Some(output_dir.to_owned()),
+            retry_failed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_retry_matches_run() -> Result<()> {
+        let output_dir = std::env::temp_dir().join("little_squid_test_retry_matches_run");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let output_dir = output_dir.to_string_lossy().into_owned();
+        let config: Config = get_opts(&output_dir, &["data"], None).try_into()?;
+        let manifest = Manifest::load(&config)?;
+        let failed = vec![FailedRange {
+            start: 20,
+            end: 30,
+            error: "timeout".to_owned(),
+        }];
+        FailureReport::save(&config, &manifest, &failed)?;
+        let path = FailureReport::get_path(&manifest);
+
+        let report = FailureReport::load(&path)?;
+        assert_eq!(report.key, manifest.get_key());
+        assert_eq!(report.failed, failed);
+
+        let retry = Some(path.to_string_lossy().into_owned());
+        let config: Config = get_opts(&output_dir, &["data"], retry.clone()).try_into()?;
+        assert_eq!(
+            config.retry_ranges,
+            Some(vec![Range { start: 20, end: 30 }])
+        );
+
+        //other fields would write files that do not match the ones of the run
+        let mismatch: Result<Config> = get_opts(&output_dir, &["blockHash"], retry).try_into();
+        assert!(mismatch.is_err());
+        Ok(())
+    }
+}
@@ -1,36 +1,562 @@
-use crate::cli::config::Dataset;
-use crate::export::export::save_to_file;
+use crate::cli::config::{Config, Range};
+use crate::export::export::{remove_files, save_to_file};
+use crate::manifest::Manifest;
+use crate::models::Block;
 use anyhow::{Error, Result};
-use crossbeam::channel::Receiver;
-use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{Semaphore, TryAcquireError};
 use tracing::debug;
+
+//pages waiting for the writer before fetching blocks
+pub const WRITE_CHANNEL_CAPACITY: usize = 64;
+//without --blocks-per-file, a file is written once this many kilobytes of a range are buffered
+const MAX_FILE_SIZE: u64 = 10 * 1024;
+
+pub enum WriteMessage {
+    //pages of a fetch chunk in block order, holding `size` kilobytes of the memory budget
+    Blocks {
+        range: Range,
+        blocks: Vec<Block>,
+        size: u64,
+    },
+    //sent once every block of the range has been sent to the writer
+    RangeDone(Range),
+    //a page does not fit in the budget, buffered blocks have to be written out
+    Flush,
+}
+
+/// Bytes of fetched blocks that may be in memory at once. Fetching waits
+/// until the writer has written enough to release them.
+pub struct MemoryBudget {
+    semaphore: Semaphore,
+    //permits are kilobytes, a u32 of bytes would cap the budget at 4GB
+    total_kb: u32,
+    //fetch tasks waiting for the budget
+    waiting: AtomicUsize,
+}
+
+//the sending half of the writer, shared by the fetch tasks
+#[derive(Clone)]
+pub struct WriteHandle {
+    write_tx: Sender<WriteMessage>,
+    budget: Arc<MemoryBudget>,
+}
+
+impl MemoryBudget {
+    pub fn new(bytes: u64) -> MemoryBudget {
+        let total_kb = (bytes / 1024).clamp(1, u32::MAX as u64) as u32;
+        MemoryBudget {
+            semaphore: Semaphore::new(total_kb as usize),
+            total_kb,
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    //kilobytes taken by a page, one larger than the budget takes all of it
+    pub fn get_size(&self, bytes: u64) -> u64 {
+        (bytes / 1024 + 1).min(self.total_kb as u64)
+    }
+
+    //false when the budget is used up
+    pub fn try_reserve(&self, size: u64) -> Result<bool> {
+        match self.semaphore.try_acquire_many(size as u32) {
+            Ok(permits) => {
+                permits.forget();
+                Ok(true)
+            }
+            Err(TryAcquireError::NoPermits) => Ok(false),
+            Err(TryAcquireError::Closed) => Err(Error::msg("The writer has stopped")),
+        }
+    }
+
+    //fails once the writer has stopped, nothing would release the budget anymore
+    pub async fn reserve(&self, size: u64) -> Result<()> {
+        let permits = self
+            .semaphore
+            .acquire_many(size as u32)
+            .await
+            .map_err(|_| Error::msg("The writer has stopped"))?;
+        permits.forget();
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.semaphore.close();
+    }
+
+    pub fn release(&self, size: u64) {
+        self.semaphore.add_permits(size as usize);
+    }
+
+    //fetch tasks may be waiting, so buffered blocks should be written out
+    pub fn is_low(&self) -> bool {
+        self.waiting.load(Ordering::SeqCst) > 0
+            || (self.semaphore.available_permits() as u64) < self.total_kb as u64 / 2
+    }
+}
+
+impl WriteHandle {
+    pub fn new(write_tx: Sender<WriteMessage>, budget: Arc<MemoryBudget>) -> WriteHandle {
+        WriteHandle { write_tx, budget }
+    }
+
+    //`bytes` is the size of the response the blocks were parsed from
+    pub async fn send_blocks(&self, range: &Range, blocks: Vec<Block>, bytes: u64) -> Result<()> {
+        let size = self.budget.get_size(bytes);
+        if !self.budget.try_reserve(size)? {
+            //buffers below the file size would otherwise hold the budget forever,
+            //every page the writer gets while we wait is written out right away
+            self.budget.waiting.fetch_add(1, Ordering::SeqCst);
+            let reserved = self.flush_and_reserve(size).await;
+            self.budget.waiting.fetch_sub(1, Ordering::SeqCst);
+            reserved?;
+        }
+        let message = WriteMessage::Blocks {
+            range: range.clone(),
+            blocks,
+            size,
+        };
+        self.write_tx
+            .send(message)
+            .await
+            .map_err(|e| Error::msg(format!("Error sending blocks: {}", e)))
+    }
+
+    async fn flush_and_reserve(&self, size: u64) -> Result<()> {
+        self.write_tx
+            .send(WriteMessage::Flush)
+            .await
+            .map_err(|e| Error::msg(format!("Error sending blocks: {}", e)))?;
+        self.budget.reserve(size).await
+    }
+
+    pub async fn send_done(&self, range: Range) -> Result<()> {
+        self.write_tx
+            .send(WriteMessage::RangeDone(range))
+            .await
+            .map_err(|e| Error::msg(format!("Error sending blocks: {}", e)))
+    }
+}
+
+//blocks of one fetch chunk that have not been written yet
+struct RangeBuffer {
+    //first block not written yet
+    start: u64,
+    blocks: Vec<Block>,
+    size: u64,
+}
+
+//blocks of one `--blocks-per-file` file, held until every chunk of the file is done
+#[derive(Default)]
+struct FileBuffer {
+    blocks: Vec<Block>,
+    done: Vec<Range>,
+}
+
 pub fn write_loop(
-    dataset: Dataset,
-    fields: Vec<String>,
-    write_rx: Receiver<Vec<Value>>,
+    config: Config,
+    manifest: Manifest,
+    write_rx: Receiver<WriteMessage>,
+    budget: Arc<MemoryBudget>,
+) -> Result<()> {
+    let result = write_messages(config, manifest, write_rx, &budget);
+    //wake up the fetch tasks waiting for the budget, they fail instead of hanging
+    budget.close();
+    result
+}
+
+fn write_messages(
+    config: Config,
+    mut manifest: Manifest,
+    mut write_rx: Receiver<WriteMessage>,
+    budget: &MemoryBudget,
 ) -> Result<()> {
+    let mut files: BTreeMap<u64, FileBuffer> = BTreeMap::new();
+    let mut ranges: HashMap<u64, RangeBuffer> = HashMap::new();
     loop {
-        //receive the bytes from stats
-
-        //let buffer = write_rx.recv().unwrap();
-        let buffer = match write_rx.recv() {
-            Ok(b) => b,
-            Err(e) => {
-                if e.to_string()
-                    .contains("receiving on an empty and disconnected channel")
-                {
-                    debug!("Channel closed");
-                    break;
-                } else {
-                    return Err(Error::msg(e));
-                }
+        let message = match write_rx.blocking_recv() {
+            Some(m) => m,
+            None => {
+                debug!("Channel closed");
+                break;
             }
         };
-        if buffer.is_empty() {
-            debug!("Buffer is empty");
-            break;
+        match message {
+            WriteMessage::Blocks {
+                range,
+                blocks: buffer,
+                size,
+            } => {
+                if buffer.is_empty() {
+                    debug!("Buffer is empty");
+                    budget.release(size);
+                    continue;
+                }
+                match config.blocks_per_file {
+                    //files wait for all of their chunks, so they are not bounded by the budget
+                    Some(blocks_per_file) => {
+                        budget.release(size);
+                        for block in buffer {
+                            files
+                                .entry(block.header.number / blocks_per_file)
+                                .or_default()
+                                .blocks
+                                .push(block);
+                        }
+                    }
+                    None => {
+                        let buffered = ranges.entry(range.start).or_insert_with(|| RangeBuffer {
+                            start: range.start,
+                            blocks: Vec::new(),
+                            size: 0,
+                        });
+                        buffered.blocks.extend(buffer);
+                        buffered.size += size;
+                        if buffered.size >= MAX_FILE_SIZE {
+                            flush_range(&config, &mut manifest, budget, buffered)?;
+                        }
+                        if budget.is_low() {
+                            for buffered in ranges.values_mut() {
+                                flush_range(&config, &mut manifest, budget, buffered)?;
+                            }
+                        }
+                    }
+                }
+            }
+            WriteMessage::RangeDone(range) => match config.blocks_per_file {
+                Some(blocks_per_file) => {
+                    //chunks are split at file boundaries, so a range belongs to one file
+                    let index = range.start / blocks_per_file;
+                    let file = files.entry(index).or_default();
+                    file.done.push(range);
+                    let bounds = get_file_bounds(&config, blocks_per_file, index);
+                    let done_blocks: u64 = file.done.iter().map(|r| r.end - r.start).sum();
+                    if done_blocks == bounds.end - bounds.start {
+                        let file = files.remove(&index).unwrap_or_default();
+                        flush_file(&config, &mut manifest, file, bounds, blocks_per_file)?;
+                    }
+                }
+                None => {
+                    if let Some(mut buffered) = ranges.remove(&range.start) {
+                        flush_range(&config, &mut manifest, budget, &mut buffered)?;
+                    }
+                    manifest.mark_completed(range)?;
+                }
+            },
+            WriteMessage::Flush => {
+                for buffered in ranges.values_mut() {
+                    flush_range(&config, &mut manifest, budget, buffered)?;
+                }
+            }
+        }
+    }
+    //anything left by a range that stopped without being marked done
+    for buffered in ranges.values_mut() {
+        flush_range(&config, &mut manifest, budget, buffered)?;
+    }
+    //the last file of a range that does not end on a boundary
+    if let Some(blocks_per_file) = config.blocks_per_file {
+        for (index, file) in files {
+            let mut bounds = get_file_bounds(&config, blocks_per_file, index);
+            bounds.end = bounds.end.min(config.range.end);
+            let done_blocks: u64 = file.done.iter().map(|r| r.end - r.start).sum();
+            if done_blocks == bounds.end - bounds.start {
+                flush_file(&config, &mut manifest, file, bounds, blocks_per_file)?;
+            } else {
+                //some chunk failed, the whole file is in the failure report
+                eprintln!(
+                    "Skipping incomplete file for blocks {}-{}",
+                    bounds.start,
+                    bounds.end - 1
+                );
+            }
         }
-        save_to_file(dataset, &fields, buffer)?;
     }
     Ok(())
 }
+
+//the written blocks go to the manifest right away, so a rerun after a crash does not write them twice
+fn flush_range(
+    config: &Config,
+    manifest: &mut Manifest,
+    budget: &MemoryBudget,
+    buffered: &mut RangeBuffer,
+) -> Result<()> {
+    if let Some(last) = buffered.blocks.last() {
+        let written = Range {
+            start: buffered.start,
+            end: last.header.number + 1,
+        };
+        save_to_file(config, std::mem::take(&mut buffered.blocks), None)?;
+        manifest.mark_completed(written.clone())?;
+        buffered.start = written.end;
+    }
+    budget.release(std::mem::take(&mut buffered.size));
+    Ok(())
+}
+
+//`[k*N, (k+1)*N)`, cut at the start of the requested range
+fn get_file_bounds(config: &Config, blocks_per_file: u64, index: u64) -> Range {
+    Range {
+        start: (index * blocks_per_file).max(config.range.start),
+        end: (index + 1) * blocks_per_file,
+    }
+}
+
+//a file cut short by an earlier range is replaced, a longer range would write an overlapping one
+fn flush_file(
+    config: &Config,
+    manifest: &mut Manifest,
+    mut file: FileBuffer,
+    bounds: Range,
+    blocks_per_file: u64,
+) -> Result<()> {
+    //chunks finish out of order
+    file.blocks.sort_by_key(|block| block.header.number);
+    for partial in manifest.take_partial_files(&bounds) {
+        //the earlier blocks are fetched again, so their partitions and names are known
+        let blocks: Vec<&Block> = file
+            .blocks
+            .iter()
+            .filter(|block| {
+                partial.start <= block.header.number && block.header.number < partial.end
+            })
+            .collect();
+        remove_files(config, &blocks, partial)?;
+    }
+    if bounds.end - bounds.start < blocks_per_file {
+        manifest.add_partial_file(bounds.clone());
+    }
+    if !file.blocks.is_empty() {
+        save_to_file(config, file.blocks, Some(bounds))?;
+    }
+    for range in file.done {
+        manifest.mark_completed(range)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_loop, MemoryBudget, WriteHandle, WriteMessage, MAX_FILE_SIZE};
+    use crate::cli::config::{Config, Range};
+    use crate::cli::opts::Opts;
+    use crate::manifest::Manifest;
+    use crate::models::{Block, BlockHeader};
+    use anyhow::Result;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn get_config(name: &str, memory_budget: u64) -> Result<(Config, PathBuf)> {
+        let output_dir = std::env::temp_dir().join(format!("little_squid_test_{}", name));
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let config = Opts {
+            dataset: Some("logs".to_owned()),
+            range: Some("0:200".to_owned()),
+            output_dir: Some(output_dir.to_string_lossy().into_owned()),
+            memory_budget: Some(memory_budget),
+            ..Default::default()
+        }
+        .try_into()?;
+        Ok((config, output_dir.join("ethereum-mainnet")))
+    }
+
+    //blocks without logs, the files only need their names checked
+    fn get_blocks(range: std::ops::Range<u64>) -> Vec<Block> {
+        range
+            .map(|number| Block {
+                header: BlockHeader {
+                    number,
+                    ..Default::default()
+                },
+                transactions: vec![],
+                logs: vec![],
+                traces: vec![],
+                state_diffs: vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_memory_budget() -> Result<()> {
+        let budget = MemoryBudget::new(16 * MB);
+        assert_eq!(budget.get_size(100 * MB), 16 * 1024);
+        assert!(budget.try_reserve(7 * 1024)?);
+        assert!(!budget.is_low());
+        assert!(budget.try_reserve(2 * 1024)?);
+        assert!(budget.is_low());
+        assert!(!budget.try_reserve(8 * 1024)?);
+        budget.release(9 * 1024);
+        assert!(!budget.is_low());
+        assert!(budget.try_reserve(16 * 1024)?);
+        budget.release(16 * 1024);
+
+        budget.close();
+        assert!(budget.try_reserve(1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_on_size_low_memory_and_done() -> Result<()> {
+        let (config, folder) = get_config("flush_on_size_low_memory_and_done", 64)?;
+        let manifest = Manifest::load(&config)?;
+        let budget = Arc::new(MemoryBudget::new(config.memory_budget));
+        let (write_tx, write_rx) = tokio::sync::mpsc::channel(16);
+        let send = |range: Range, blocks: Vec<Block>, size: u64| -> Result<()> {
+            assert!(budget.try_reserve(size)?);
+            write_tx.try_send(WriteMessage::Blocks {
+                range,
+                blocks,
+                size,
+            })?;
+            Ok(())
+        };
+        let first = Range { start: 0, end: 50 };
+        let second = Range {
+            start: 50,
+            end: 100,
+        };
+        let third = Range {
+            start: 100,
+            end: 200,
+        };
+        //a full file is written before its range is done
+        send(first.clone(), get_blocks(0..20), MAX_FILE_SIZE)?;
+        send(first.clone(), get_blocks(20..50), 100)?;
+        write_tx.try_send(WriteMessage::RangeDone(first))?;
+        send(second.clone(), get_blocks(50..60), 100)?;
+        //more than half of the budget, every buffer is written out
+        send(third, get_blocks(100..110), 40 * 1024)?;
+        drop(write_tx);
+
+        write_loop(config.clone(), manifest, write_rx, budget.clone())?;
+        for name in ["logs_0-19", "logs_20-49", "logs_50-59", "logs_100-109"] {
+            assert!(
+                folder.join(format!("{}.parquet", name)).exists(),
+                "{}",
+                name
+            );
+        }
+        let manifest = Manifest::load(&config)?;
+        assert_eq!(
+            manifest.get_missing(&second),
+            vec![Range {
+                start: 60,
+                end: 100
+            }]
+        );
+        assert!(manifest.is_completed(&Range { start: 0, end: 60 }));
+        Ok(())
+    }
+
+    //buffers below the file size used to hold the budget while the next page waited for it
+    #[tokio::test]
+    async fn test_waiting_page_flushes_buffers() -> Result<()> {
+        let (config, folder) = get_config("waiting_page_flushes_buffers", 16)?;
+        let manifest = Manifest::load(&config)?;
+        let budget = Arc::new(MemoryBudget::new(config.memory_budget));
+        let (write_tx, write_rx) = tokio::sync::mpsc::channel(16);
+        let writer_budget = budget.clone();
+        let writer =
+            std::thread::spawn(move || write_loop(config, manifest, write_rx, writer_budget));
+        let handle = WriteHandle::new(write_tx, budget.clone());
+
+        let pages = [
+            (0..10, 7 * MB),
+            (10..20, 10 * MB),
+            //exactly the budget
+            (20..30, 16 * MB - 1024),
+            //larger than the budget
+            (30..40, 100 * MB),
+        ];
+        for (blocks, bytes) in pages {
+            let range = Range {
+                start: blocks.start,
+                end: blocks.end,
+            };
+            let send = handle.send_blocks(&range, get_blocks(blocks), bytes);
+            tokio::time::timeout(Duration::from_secs(10), send).await??;
+        }
+        drop(handle);
+        writer.join().unwrap()?;
+        for name in ["logs_0-9", "logs_10-19", "logs_20-29", "logs_30-39"] {
+            assert!(
+                folder.join(format!("{}.parquet", name)).exists(),
+                "{}",
+                name
+            );
+        }
+
+        //the writer has stopped, waiting for the budget fails instead of hanging
+        assert!(budget.reserve(1).await.is_err());
+        Ok(())
+    }
+
+    //an open-ended range ends at a new height on every run
+    #[test]
+    fn test_extended_range_replaces_partial_file() -> Result<()> {
+        let output_dir = std::env::temp_dir().join("little_squid_test_extended_range");
+        let _ = std::fs::remove_dir_all(&output_dir);
+        let folder = output_dir.join("ethereum-mainnet");
+        let get_config = |range: &str| -> Result<Config> {
+            Opts {
+                dataset: Some("logs".to_owned()),
+                range: Some(range.to_owned()),
+                output_dir: Some(output_dir.to_string_lossy().into_owned()),
+                blocks_per_file: Some(10),
+                ..Default::default()
+            }
+            .try_into()
+        };
+        let run = |config: Config, chunks: Vec<Range>| -> Result<()> {
+            let manifest = Manifest::load(&config)?;
+            let budget = Arc::new(MemoryBudget::new(config.memory_budget));
+            let (write_tx, write_rx) = tokio::sync::mpsc::channel(16);
+            for chunk in chunks {
+                assert!(budget.try_reserve(1)?);
+                write_tx.try_send(WriteMessage::Blocks {
+                    range: chunk.clone(),
+                    blocks: get_blocks(chunk.start..chunk.end),
+                    size: 1,
+                })?;
+                write_tx.try_send(WriteMessage::RangeDone(chunk))?;
+            }
+            drop(write_tx);
+            write_loop(config, manifest, write_rx, budget)
+        };
+
+        run(
+            get_config("0:25")?,
+            vec![
+                Range { start: 0, end: 10 },
+                Range { start: 10, end: 20 },
+                Range { start: 20, end: 25 },
+            ],
+        )?;
+        assert!(folder.join("logs_20-24.parquet").exists());
+
+        let config = get_config("0:37")?;
+        run(
+            config.clone(),
+            vec![Range { start: 20, end: 30 }, Range { start: 30, end: 37 }],
+        )?;
+        for name in ["logs_0-9", "logs_10-19", "logs_20-29", "logs_30-36"] {
+            assert!(
+                folder.join(format!("{}.parquet", name)).exists(),
+                "{}",
+                name
+            );
+        }
+        assert!(!folder.join("logs_20-24.parquet").exists());
+        let manifest = Manifest::load(&config)?;
+        assert_eq!(manifest.partial_files, vec![Range { start: 30, end: 37 }]);
+        assert!(manifest.is_completed(&Range { start: 0, end: 37 }));
+        Ok(())
+    }
+}
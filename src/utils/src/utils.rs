@@ -13,6 +13,38 @@ pub fn hex_str_to_u64(hex_str: &str) -> Result<u64, std::num::ParseIntError> {
     u64::from_str_radix(trimmed_hex_str, 16)
 }
 
+//`0x`-prefixed hex as raw bytes, `0x` alone is empty
+pub fn hex_to_bytes(hex_str: &str) -> Result<Vec<u8>, hex::FromHexError> {
+    hex::decode(hex_str.trim_start_matches("0x"))
+}
+
+pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    // Stable across runs and toolchains, unlike std's DefaultHasher
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+//utc date of a unix timestamp as YYYY-MM-DD
+pub fn timestamp_to_date(timestamp: u64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
 pub fn sizeof_val(v: &serde_json::Value) -> usize {
     std::mem::size_of::<serde_json::Value>()
         + match v {
@@ -32,3 +64,20 @@ pub fn sizeof_val(v: &serde_json::Value) -> usize {
                 .sum(),
         }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::timestamp_to_date;
+
+    #[test]
+    fn test_timestamp_to_date() {
+        assert_eq!(timestamp_to_date(0), "1970-01-01");
+        //leap days, including the one of a year divisible by 400
+        assert_eq!(timestamp_to_date(1709208000), "2024-02-29");
+        assert_eq!(timestamp_to_date(951782400), "2000-02-29");
+        assert_eq!(timestamp_to_date(1709251200), "2024-03-01");
+        //the last second of a year and the first one of the next
+        assert_eq!(timestamp_to_date(1704067199), "2023-12-31");
+        assert_eq!(timestamp_to_date(1704067200), "2024-01-01");
+    }
+}